@@ -1,5 +1,4 @@
 use crate::FixedBufPool;
-use off64::usz;
 use std::borrow::Borrow;
 use std::borrow::BorrowMut;
 use std::cmp::Ordering;
@@ -7,13 +6,52 @@ use std::fmt;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::io;
+use std::io::Write;
+use std::mem::align_of;
+use std::mem::size_of;
 use std::ops::Deref;
 use std::ops::DerefMut;
 use std::ops::Index;
 use std::ops::IndexMut;
+use std::ops::RangeBounds;
+use std::ptr;
 use std::slice;
 use std::slice::SliceIndex;
 
+const DEBUG_PREVIEW_LEN: usize = 16;
+
+/// An `io::Write` that fills a borrowed FixedBuf from the start, so that code written against `Buf`'s `Write` can target a FixedBuf without converting. As a FixedBuf can't grow, writes stop at its capacity: `write` returns how much fit, so `write_all` fails with `WriteZero` once it's full. Created with `FixedBuf::as_growable_view`; call `finish` to get the written length once done.
+pub struct BufView<'a> {
+  buf: &'a mut FixedBuf,
+  filled: usize,
+}
+
+impl<'a> BufView<'a> {
+  /// How many bytes have been written, from the start of the buffer.
+  pub fn filled(&self) -> usize {
+    self.filled
+  }
+
+  /// Ends the view, returning how many bytes were written, so that the count outlives the borrow of the FixedBuf.
+  pub fn finish(self) -> usize {
+    self.filled
+  }
+}
+
+impl<'a> Write for BufView<'a> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = buf.len().min(self.buf.capacity() - self.filled);
+    self.buf[self.filled..self.filled + n].copy_from_slice(&buf[..n]);
+    self.filled += n;
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
 pub struct FixedBuf {
   pub(crate) ptr_and_cap: usize,
   pub(crate) pool: FixedBufPool,
@@ -32,6 +70,19 @@ impl FixedBuf {
     &self.pool
   }
 
+  /// The alignment of the pool this FixedBuf was allocated from.
+  pub fn alignment(&self) -> usize {
+    self.pool.inner.align
+  }
+
+  /// Returns a `BufView` that writes into this buffer from the start, tracking how much has been written, which `BufView::finish` returns.
+  pub fn as_growable_view(&mut self) -> BufView<'_> {
+    BufView {
+      buf: self,
+      filled: 0,
+    }
+  }
+
   pub fn as_slice(&self) -> &[u8] {
     unsafe { slice::from_raw_parts(self.ptr(), self.capacity()) }
   }
@@ -40,10 +91,88 @@ impl FixedBuf {
     unsafe { slice::from_raw_parts_mut(self.ptr(), self.capacity()) }
   }
 
+  /// Views the buffer as a slice of `T`, e.g. an array of integers or `#[repr(C)]` records for direct I/O. Panics if the capacity isn't a multiple of the size of `T`, or the pool's alignment doesn't satisfy `T`'s alignment.
+  ///
+  /// # Safety
+  ///
+  /// Every bit pattern must be a valid `T`, as the buffer may contain arbitrary bytes.
+  pub unsafe fn as_slice_of<T: Copy>(&self) -> &[T] {
+    let len = self.typed_len::<T>();
+    unsafe { slice::from_raw_parts(self.ptr() as *const T, len) }
+  }
+
+  /// Mutable version of `as_slice_of`.
+  ///
+  /// # Safety
+  ///
+  /// Every bit pattern must be a valid `T`, as the buffer may contain arbitrary bytes.
+  pub unsafe fn as_mut_slice_of<T: Copy>(&mut self) -> &mut [T] {
+    let len = self.typed_len::<T>();
+    unsafe { slice::from_raw_parts_mut(self.ptr() as *mut T, len) }
+  }
+
+  fn typed_len<T>(&self) -> usize {
+    assert!(
+      size_of::<T>() > 0,
+      "cannot view a buffer as zero-sized values"
+    );
+    assert_eq!(
+      self.capacity() % size_of::<T>(),
+      0,
+      "capacity isn't a multiple of the value size"
+    );
+    assert!(
+      self.alignment() >= align_of::<T>(),
+      "pool alignment of {} is less than the value alignment of {}",
+      self.alignment(),
+      align_of::<T>()
+    );
+    self.capacity() / size_of::<T>()
+  }
+
   pub fn capacity(&self) -> usize {
     let l2 = self.ptr_and_cap & (self.pool.inner.align - 1);
     1 << l2
   }
+
+  /// Whether the data pointer is aligned to `align`. `align` must be a power of two.
+  pub fn is_aligned_to(&self, align: usize) -> bool {
+    assert!(align.is_power_of_two());
+    self.ptr() as usize & (align - 1) == 0
+  }
+
+  /// Always false, as a FixedBuf's contents are its entire capacity, which is never zero.
+  pub fn is_empty(&self) -> bool {
+    false
+  }
+
+  /// Same as `capacity()`, as a FixedBuf has no separate logical length.
+  pub fn len(&self) -> usize {
+    self.capacity()
+  }
+
+  /// Copies each half of the contents into a new FixedBuf of half the capacity from the same pool, and returns this buffer's slab to the pool. Panics if the capacity is one byte.
+  pub fn split_half(self) -> (FixedBuf, FixedBuf) {
+    let half = self.capacity() / 2;
+    assert!(half > 0, "cannot split a one-byte buffer");
+    let (left, right) = self.as_slice().split_at(half);
+    (
+      self.pool.allocate_from_data(left),
+      self.pool.allocate_from_data(right),
+    )
+  }
+
+  /// Zeroes the entire buffer.
+  pub fn zero(&mut self) {
+    unsafe { ptr::write_bytes(self.ptr(), 0, self.capacity()) };
+  }
+
+  /// Zeroes the bytes in `range`, panicking if it's out of bounds.
+  pub fn zero_range(&mut self, range: impl RangeBounds<usize>) {
+    let region =
+      &mut self.as_mut_slice()[(range.start_bound().cloned(), range.end_bound().cloned())];
+    unsafe { ptr::write_bytes(region.as_mut_ptr(), 0, region.len()) };
+  }
 }
 
 impl AsRef<[u8]> for FixedBuf {
@@ -77,11 +206,25 @@ impl Clone for FixedBuf {
   }
 }
 
+/// Only the first `DEBUG_PREVIEW_LEN` bytes are shown by `{:?}`, as direct I/O buffers are often very large. Use `{:#?}` to print the full contents.
 impl Debug for FixedBuf {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    f.debug_struct("FixedBuf")
-      .field("data", &self.as_slice())
-      .finish()
+    let alternate = f.alternate();
+    let mut s = f.debug_struct("FixedBuf");
+    s.field("cap", &self.capacity());
+    if alternate {
+      s.field("data", &self.as_slice());
+    } else {
+      let data = self.as_slice();
+      let preview = &data[..data.len().min(DEBUG_PREVIEW_LEN)];
+      let more = if data.len() > DEBUG_PREVIEW_LEN {
+        "…"
+      } else {
+        ""
+      };
+      s.field("data", &format_args!("{:02x?}{}", preview, more));
+    };
+    s.finish()
   }
 }
 
@@ -101,10 +244,7 @@ impl DerefMut for FixedBuf {
 
 impl Drop for FixedBuf {
   fn drop(&mut self) {
-    self.pool.inner.sizes[usz!(self.capacity().ilog2())]
-      .0
-      .lock()
-      .push_back(self.ptr_and_cap);
+    self.pool.inner.release(self.ptr_and_cap);
   }
 }
 
@@ -132,6 +272,7 @@ impl<I: SliceIndex<[u8]>> IndexMut<I> for FixedBuf {
   }
 }
 
+/// A FixedBuf has no separate logical length, so its contents are all `capacity()` bytes. Buffers are ordered lexicographically by those contents, which is consistent with `PartialEq`: a buffer that's a prefix of a larger one (e.g. 512 bytes vs. 1024 bytes with the same first 512) orders before it, regardless of the larger one's remaining bytes.
 impl Ord for FixedBuf {
   fn cmp(&self, other: &Self) -> Ordering {
     self.as_slice().cmp(other.as_slice())
@@ -144,8 +285,221 @@ impl PartialEq for FixedBuf {
   }
 }
 
+// Kept as the slice comparison, which is equivalent to `Ord::cmp`.
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for FixedBuf {
   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
     self.as_slice().partial_cmp(other.as_slice())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::buf::FixedBuf;
+  use crate::FixedBufPool;
+  use std::cmp::Ordering;
+
+  #[test]
+  fn debug_previews_unless_alternate() {
+    let pool = FixedBufPool::new();
+    let mut buf = pool.allocate_with_zeros(32);
+    buf[0] = 0xab;
+    assert_eq!(
+      format!("{:?}", buf),
+      "FixedBuf { cap: 32, data: [ab, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00]… }"
+    );
+    let full = format!("{:#?}", buf);
+    assert!(full.contains("cap: 32"));
+    assert_eq!(full.matches("0,").count(), 31);
+    assert!(full.contains("171,"));
+  }
+
+  #[test]
+  fn orders_by_contents_across_classes() {
+    let pool = FixedBufPool::new();
+    let short = pool.allocate_from_data([1u8; 512]);
+    let mut long = [1u8; 1024];
+    let long_zeros = pool.allocate_from_data(long);
+    long[512..].fill(0xff);
+    let long_ones = pool.allocate_from_data(long);
+    // A shorter buffer that's a prefix of a longer one orders first, whatever the longer one's remaining bytes are.
+    assert!(short < long_zeros);
+    assert!(short < long_ones);
+    assert!(long_zeros < long_ones);
+    // A difference within the shared prefix decides the order before the lengths do.
+    let short_greater = pool.allocate_from_data([2u8; 512]);
+    assert!(short_greater > long_ones);
+    assert_eq!(short.cmp(&short.clone()), Ordering::Equal);
+
+    let mut sorted = vec![
+      short_greater.clone(),
+      long_ones.clone(),
+      short.clone(),
+      long_zeros.clone(),
+    ];
+    sorted.sort();
+    assert_eq!(sorted, vec![short, long_zeros, long_ones, short_greater]);
+  }
+
+  #[test]
+  fn zero_and_zero_range() {
+    let pool = FixedBufPool::new();
+    let mut buf = pool.allocate_from_data([0xffu8; 64]);
+    buf.zero_range(8..16);
+    assert!(buf[..8].iter().all(|&b| b == 0xff));
+    assert!(buf[8..16].iter().all(|&b| b == 0));
+    assert!(buf[16..].iter().all(|&b| b == 0xff));
+    buf.zero_range(60..);
+    assert!(buf[16..60].iter().all(|&b| b == 0xff));
+    assert!(buf[60..].iter().all(|&b| b == 0));
+    buf.zero();
+    assert!(buf.iter().all(|&b| b == 0));
+  }
+
+  #[test]
+  #[should_panic]
+  fn zero_range_out_of_bounds_panics() {
+    let pool = FixedBufPool::new();
+    let mut buf = pool.allocate_with_zeros(64);
+    buf.zero_range(32..65);
+  }
+
+  #[test]
+  fn inherent_len_isnt_ambiguous_with_trait_len() {
+    trait Sector {
+      fn len(&self) -> usize;
+    }
+
+    impl Sector for FixedBuf {
+      fn len(&self) -> usize {
+        FixedBuf::len(self) / 512
+      }
+    }
+
+    fn sectors<T: Sector>(s: &T) -> usize {
+      s.len()
+    }
+
+    let pool = FixedBufPool::new();
+    let buf = pool.allocate_with_zeros(4096);
+    assert_eq!(buf.len(), 4096);
+    assert!(!buf.is_empty());
+    assert_eq!(sectors(&buf), 8);
+  }
+
+  #[test]
+  fn alignment_matches_the_pool() {
+    for align in [64, 512, 4096] {
+      let pool = FixedBufPool::with_alignment(align);
+      let buf = pool.allocate_with_zeros(1024);
+      assert_eq!(buf.alignment(), align);
+      assert!(buf.is_aligned_to(align));
+      assert!(buf.is_aligned_to(64));
+    }
+  }
+
+  #[test]
+  fn split_half_copies_each_half() {
+    let pool = FixedBufPool::new();
+    let data: Vec<u8> = (0..16).collect();
+    let buf = pool.allocate_from_data(&data);
+    let (left, right) = buf.split_half();
+    assert_eq!(left.capacity(), 8);
+    assert_eq!(right.capacity(), 8);
+    assert_eq!(left.as_slice(), &data[..8]);
+    assert_eq!(right.as_slice(), &data[8..]);
+    assert_eq!(pool.stats().classes[4].retained, 1);
+  }
+
+  #[test]
+  #[should_panic(expected = "cannot split a one-byte buffer")]
+  fn split_half_panics_on_one_byte_buffer() {
+    let pool = FixedBufPool::new();
+    let _ = pool.allocate_with_zeros(1).split_half();
+  }
+
+  #[test]
+  fn as_slice_of_u64_views_native_endian_words() {
+    let pool = FixedBufPool::new();
+    let mut buf = pool.allocate_with_zeros(32);
+    buf[8..16].copy_from_slice(&7u64.to_ne_bytes());
+    let words = unsafe { buf.as_slice_of::<u64>() };
+    assert_eq!(words, [0, 7, 0, 0]);
+    let words = unsafe { buf.as_mut_slice_of::<u64>() };
+    words[3] = u64::MAX;
+    assert_eq!(&buf[24..], &[0xff; 8]);
+  }
+
+  #[test]
+  fn as_slice_of_repr_c_struct() {
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Record {
+      id: u32,
+      flags: u16,
+      kind: u16,
+    }
+
+    let pool = FixedBufPool::new();
+    let mut buf = pool.allocate_with_zeros(64);
+    let records = unsafe { buf.as_mut_slice_of::<Record>() };
+    assert_eq!(records.len(), 8);
+    records[1] = Record {
+      id: 42,
+      flags: 1,
+      kind: 2,
+    };
+    assert_eq!(&buf[8..12], &42u32.to_ne_bytes());
+    let records = unsafe { buf.as_slice_of::<Record>() };
+    assert_eq!(records[1].id, 42);
+    assert_eq!(records[1].kind, 2);
+  }
+
+  #[test]
+  #[should_panic(expected = "capacity isn't a multiple of the value size")]
+  fn as_slice_of_panics_on_uneven_capacity() {
+    let pool = FixedBufPool::new();
+    let buf = pool.allocate_with_zeros(4);
+    let _ = unsafe { buf.as_slice_of::<[u8; 3]>() };
+  }
+
+  #[test]
+  fn growable_view_fills_up_to_capacity() {
+    use std::io::Write;
+
+    let pool = FixedBufPool::new();
+    let mut buf = pool.allocate_with_zeros(8);
+    let mut view = buf.as_growable_view();
+    view.write_all(b"abc").unwrap();
+    write!(view, "{}", 1234).unwrap();
+    assert_eq!(view.filled(), 7);
+    // Only the byte that fits is written.
+    assert_eq!(view.write(b"xyz").unwrap(), 1);
+    assert_eq!(view.filled(), 8);
+    assert_eq!(view.write(b"z").unwrap(), 0);
+    let err = view.write_all(b"z").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+    assert_eq!(buf.as_slice(), b"abc1234x");
+  }
+
+  #[test]
+  fn growable_view_finish_reports_length_after_view_ends() {
+    use std::io::Write;
+
+    let pool = FixedBufPool::new();
+    let mut buf = pool.allocate_with_zeros(16);
+    let written = {
+      let mut view = buf.as_growable_view();
+      write!(view, "len={}", 42).unwrap();
+      view.finish()
+    };
+    assert_eq!(written, 6);
+    assert_eq!(&buf[..written], b"len=42");
+
+    // A full view reports the capacity.
+    let mut view = buf.as_growable_view();
+    assert!(view.write_all(&[1; 20]).is_err());
+    assert_eq!(view.finish(), 16);
+    assert_eq!(buf.as_slice(), &[1; 16]);
+  }
+}
@@ -1,23 +1,72 @@
 pub mod buf;
+pub mod stats;
 
 use buf::FixedBuf;
 use off64::usz;
+use stats::PoolStats;
+use stats::SizeClassStats;
 use std::alloc::alloc_zeroed;
+use std::alloc::dealloc;
 use std::alloc::Layout;
 use std::cmp::max;
 use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
 use std::mem::size_of;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 // TODO Benchmark parking_lot::Mutex<VecDeque<>> against crossbeam_channel and flume. Also consider one allocator per thread, which could waste a lot of memory but also be very quick.
-#[derive(Clone, Default)]
-struct BufPoolForSize(Arc<parking_lot::Mutex<VecDeque<usize>>>);
+#[derive(Default)]
+struct BufPoolForSize {
+  retained: parking_lot::Mutex<VecDeque<usize>>,
+  /// Buffers of this size currently allocated and not yet dropped.
+  live: AtomicUsize,
+  /// The highest `live` has ever been.
+  peak_live: AtomicUsize,
+}
 
 struct Inner {
   align: usize,
+  max_retained_per_class: usize,
   sizes: Vec<BufPoolForSize>,
 }
 
+impl Inner {
+  /// Frees the slab of a packed pointer and capacity. The capacity is recovered from the low bits, like `FixedBuf::capacity`.
+  unsafe fn dealloc_packed(&self, ptr_and_cap: usize) {
+    let ptr = (ptr_and_cap & !(self.align - 1)) as *mut u8;
+    let cap = 1 << (ptr_and_cap & (self.align - 1));
+    dealloc(ptr, Layout::from_size_align(cap, self.align).unwrap());
+  }
+
+  /// Called when a FixedBuf is dropped to return its slab to the pool.
+  fn release(&self, ptr_and_cap: usize) {
+    let size = &self.sizes[ptr_and_cap & (self.align - 1)];
+    size.live.fetch_sub(1, Ordering::Relaxed);
+    let mut retained = size.retained.lock();
+    if retained.len() >= self.max_retained_per_class {
+      // Release lock before freeing.
+      drop(retained);
+      unsafe { self.dealloc_packed(ptr_and_cap) };
+      return;
+    };
+    retained.push_back(ptr_and_cap);
+  }
+}
+
+impl Drop for Inner {
+  fn drop(&mut self) {
+    for i in 0..self.sizes.len() {
+      let retained = std::mem::take(self.sizes[i].retained.get_mut());
+      for ptr_and_cap in retained {
+        unsafe { self.dealloc_packed(ptr_and_cap) };
+      }
+    }
+  }
+}
+
 /// Thread-safe pool of `FixedBuf` values, which are byte arrays with a fixed length.
 /// This can be cheaply cloned to share the same underlying pool around.
 /// The maximum length is 2^64, and the minimum alignment is 64. This allows storing the pointer and capacity in one `usize`, making it much faster to move the `FixedBuf` value around.
@@ -27,22 +76,58 @@ pub struct FixedBufPool {
 }
 
 impl FixedBufPool {
-  pub fn with_alignment(align: usize) -> Self {
-    assert!(align > 64);
+  fn with_alignment_and_limits(align: usize, max_retained_per_class: usize) -> Self {
+    assert!(
+      align >= 64,
+      "alignment of {align} is less than the minimum of 64"
+    );
     assert!(align.is_power_of_two());
     let mut sizes = Vec::new();
     for _ in 0..64 {
       sizes.push(Default::default());
     }
     Self {
-      inner: Arc::new(Inner { align, sizes }),
+      inner: Arc::new(Inner {
+        align,
+        max_retained_per_class,
+        sizes,
+      }),
     }
   }
 
+  /// Panics if `align` isn't a power of two, or is less than the minimum of 64.
+  pub fn with_alignment(align: usize) -> Self {
+    Self::with_alignment_and_limits(align, usize::MAX)
+  }
+
   pub fn new() -> Self {
     Self::with_alignment(max(64, size_of::<usize>()))
   }
 
+  /// Retain at most `max_retained_per_class` slabs of each size; any more are freed when returned to the pool.
+  pub fn with_limits(max_retained_per_class: usize) -> Self {
+    Self::with_alignment_and_limits(max(64, size_of::<usize>()), max_retained_per_class)
+  }
+
+  /// Takes ownership of an existing allocation without copying it. The slab will be returned to this pool when the FixedBuf is dropped.
+  ///
+  /// # Safety
+  ///
+  /// `ptr` must have been allocated by the global allocator with `Layout::from_size_align(cap, align)`, where `align` is this pool's alignment, and must not be used or freed elsewhere afterwards. `cap` must be a power of two, and `ptr` must be aligned to this pool's alignment.
+  pub unsafe fn adopt(&self, ptr: *mut u8, cap: usize) -> FixedBuf {
+    assert!(!ptr.is_null());
+    assert!(cap.is_power_of_two());
+    let raw = ptr as usize;
+    assert_eq!(raw & (self.inner.align - 1), 0);
+    let size = &self.inner.sizes[usz!(cap.ilog2())];
+    let live = size.live.fetch_add(1, Ordering::Relaxed) + 1;
+    size.peak_live.fetch_max(live, Ordering::Relaxed);
+    FixedBuf {
+      ptr_and_cap: raw | usz!(cap.ilog2()),
+      pool: self.clone(),
+    }
+  }
+
   pub fn allocate_from_data(&self, data: impl AsRef<[u8]>) -> FixedBuf {
     let mut buf = self.allocate_with_zeros(data.as_ref().len());
     buf.copy_from_slice(data.as_ref());
@@ -55,8 +140,9 @@ impl FixedBufPool {
     assert!(cap.is_power_of_two());
     // This will round `0` to `1`.
     let cap = cap.next_power_of_two();
+    let size = &self.inner.sizes[usz!(cap.ilog2())];
     // Release lock ASAP.
-    let existing = self.inner.sizes[usz!(cap.ilog2())].0.lock().pop_front();
+    let existing = size.retained.lock().pop_front();
     let ptr_and_cap = if let Some(ptr_and_cap) = existing {
       ptr_and_cap
     } else {
@@ -67,9 +153,155 @@ impl FixedBufPool {
       assert_eq!(raw & (self.inner.align - 1), 0);
       raw | usz!(cap.ilog2())
     };
+    let live = size.live.fetch_add(1, Ordering::Relaxed) + 1;
+    size.peak_live.fetch_max(live, Ordering::Relaxed);
     FixedBuf {
       ptr_and_cap,
       pool: self.clone(),
     }
   }
+
+  /// Allocates a FixedBuf of `cap` bytes and fills it entirely from `reader`. Returns an `UnexpectedEof` error if the reader ends first. `cap` must be a power of two.
+  pub fn read_exact_from(&self, reader: &mut impl Read, cap: usize) -> io::Result<FixedBuf> {
+    let mut buf = self.allocate_with_zeros(cap);
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+  }
+
+  pub fn stats(&self) -> PoolStats {
+    PoolStats {
+      classes: self
+        .inner
+        .sizes
+        .iter()
+        .enumerate()
+        .map(|(i, size)| SizeClassStats {
+          capacity: 1 << i,
+          retained: size.retained.lock().len(),
+          live: size.live.load(Ordering::Relaxed),
+          peak_live: size.peak_live.load(Ordering::Relaxed),
+        })
+        .collect(),
+    }
+  }
+
+  /// Frees all slabs currently retained by the pool. Buffers that are still in use are unaffected, and will return to the pool as usual when dropped.
+  pub fn trim(&self) {
+    for size in self.inner.sizes.iter() {
+      // Release lock before freeing.
+      let retained = std::mem::take(&mut *size.retained.lock());
+      for ptr_and_cap in retained {
+        unsafe { self.inner.dealloc_packed(ptr_and_cap) };
+      }
+    }
+  }
+}
+
+impl Default for FixedBufPool {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::FixedBufPool;
+  use std::alloc::alloc_zeroed;
+  use std::alloc::Layout;
+
+  #[test]
+  fn stats_track_live_and_retained_slabs() {
+    let pool = FixedBufPool::new();
+    let a = pool.allocate_with_zeros(4096);
+    let b = pool.allocate_with_zeros(4096);
+    let stats = pool.stats();
+    assert_eq!(stats.class(4096).live, 2);
+    assert_eq!(stats.class(4096).retained, 0);
+    drop((a, b));
+    let stats = pool.stats();
+    assert_eq!(stats.class(4096).capacity, 4096);
+    assert_eq!(stats.class(4096).live, 0);
+    assert_eq!(stats.class(4096).peak_live, 2);
+    assert_eq!(stats.class(4096).retained, 2);
+  }
+
+  #[test]
+  fn trim_frees_retained_slabs_only() {
+    let pool = FixedBufPool::new();
+    let kept = pool.allocate_from_data([1u8; 64]);
+    drop(pool.allocate_with_zeros(4096));
+    drop(pool.allocate_with_zeros(64));
+    pool.trim();
+    let stats = pool.stats();
+    assert_eq!(stats.class(4096).retained, 0);
+    assert_eq!(stats.class(64).retained, 0);
+    assert_eq!(stats.class(64).live, 1);
+    // Buffers in use are unaffected, and still return to the pool afterwards.
+    assert_eq!(&kept[..], &[1u8; 64]);
+    drop(kept);
+    assert_eq!(pool.stats().class(64).retained, 1);
+  }
+
+  #[test]
+  fn with_limits_frees_slabs_over_the_limit() {
+    let pool = FixedBufPool::with_limits(2);
+    let bufs = (0..5)
+      .map(|_| pool.allocate_with_zeros(256))
+      .collect::<Vec<_>>();
+    drop(bufs);
+    let stats = pool.stats();
+    assert_eq!(stats.class(256).retained, 2);
+    assert_eq!(stats.class(256).live, 0);
+  }
+
+  #[test]
+  fn adopt_takes_ownership_of_an_aligned_allocation() {
+    let pool = FixedBufPool::new();
+    let layout = Layout::from_size_align(4096, 64).unwrap();
+    let ptr = unsafe { alloc_zeroed(layout) };
+    let mut buf = unsafe { pool.adopt(ptr, 4096) };
+    assert_eq!(buf.as_ptr(), ptr);
+    assert_eq!(buf.capacity(), 4096);
+    assert!(buf.iter().all(|&b| b == 0));
+    buf[0] = 1;
+    assert_eq!(pool.stats().class(4096).live, 1);
+
+    // It joins the pool, and is reused from it.
+    drop(buf);
+    assert_eq!(pool.stats().class(4096).retained, 1);
+    let buf = pool.allocate_with_zeros(4096);
+    assert_eq!(buf.as_ptr(), ptr);
+  }
+
+  #[test]
+  fn read_exact_from_fills_whole_buffer() {
+    let pool = FixedBufPool::new();
+    let data: Vec<u8> = (0..20).collect();
+    let mut reader = &data[..];
+    let buf = pool.read_exact_from(&mut reader, 16).unwrap();
+    assert_eq!(buf.as_slice(), &data[..16]);
+    assert_eq!(reader, &data[16..]);
+  }
+
+  #[test]
+  fn read_exact_from_short_reader_is_unexpected_eof() {
+    let pool = FixedBufPool::new();
+    let mut reader = &[1u8; 10][..];
+    let err = pool.read_exact_from(&mut reader, 16).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn accepts_minimum_alignment_of_64() {
+    let pool = FixedBufPool::with_alignment(64);
+    let buf = pool.allocate_with_zeros(128);
+    assert!(buf.is_aligned_to(64));
+    assert_eq!(FixedBufPool::new().allocate_with_zeros(1).alignment(), 64);
+  }
+
+  #[test]
+  #[should_panic(expected = "alignment of 32 is less than the minimum of 64")]
+  fn rejects_alignment_below_64() {
+    let _ = FixedBufPool::with_alignment(32);
+  }
 }
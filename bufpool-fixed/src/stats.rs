@@ -0,0 +1,24 @@
+/// A snapshot of a pool's usage, as returned by `FixedBufPool::stats`. Each size class is read separately, so the snapshot isn't atomic across classes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+  /// One entry per size class, in increasing order of capacity; the entry at index `i` is for capacity `2^i`.
+  pub classes: Vec<SizeClassStats>,
+}
+
+impl PoolStats {
+  /// Returns the stats for the size class of capacity `cap`.
+  pub fn class(&self, cap: usize) -> &SizeClassStats {
+    &self.classes[cap.next_power_of_two().ilog2() as usize]
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeClassStats {
+  pub capacity: usize,
+  /// Slabs sitting in the pool ready for reuse.
+  pub retained: usize,
+  /// Buffers currently allocated and not yet dropped.
+  pub live: usize,
+  /// The highest `live` has ever been.
+  pub peak_live: usize,
+}
@@ -1,3 +1,4 @@
+use bufpool::BufPool;
 use bufpool::BUFPOOL;
 use criterion::black_box;
 use criterion::criterion_group;
@@ -42,6 +43,82 @@ fn criterion_benchmark(c: &mut Criterion) {
     b.iter(|| black_box(BUFPOOL.allocate_with_zeros(size)))
   });
   c.bench_function("vec![0u8; size]", |b| b.iter(|| black_box(vec![0u8; size])));
+
+  // These drop each buffer so that it's reused, which exercises skipping the zeroing of slabs known to be zero.
+  let size = 64 * 1024;
+  let pool = BufPool::new();
+  c.bench_function("BufPool::allocate_with_zeros reused", |b| {
+    b.iter(|| drop(black_box(pool.allocate_with_zeros(size))))
+  });
+  c.bench_function("BufPool::allocate_uninitialised reused", |b| {
+    b.iter(|| drop(black_box(pool.allocate_uninitialised(size))))
+  });
+  let pool = BufPool::builder().zero_on_free(true).build();
+  c.bench_function("BufPool::allocate_with_zeros reused zero_on_free", |b| {
+    b.iter(|| drop(black_box(pool.allocate_with_zeros(size))))
+  });
+
+  // Growth should double the capacity, so this only reallocates about 20 times.
+  let size = 1024 * 1024;
+  c.bench_function("Buf::push grow to 1 MiB", |b| {
+    b.iter(|| {
+      let mut buf = BUFPOOL.allocate(1);
+      for i in 0..size {
+        buf.push(i as u8);
+      }
+      black_box(buf)
+    })
+  });
+  c.bench_function("Vec::push grow to 1 MiB", |b| {
+    b.iter(|| {
+      let mut buf = Vec::with_capacity(1);
+      for i in 0..size {
+        buf.push(i as u8);
+      }
+      black_box(buf)
+    })
+  });
+
+  // Nothing is retained, so every allocation maps a fresh slab whose pages haven't been faulted in yet.
+  let size = 1024 * 1024;
+  for prefault in [false, true] {
+    let pool = BufPool::builder()
+      .mmap_backed()
+      .max_retained_per_class(0)
+      .prefault(prefault)
+      .build();
+    let name = if prefault { " prefault" } else { "" };
+    c.bench_function(&format!("BufPool::allocate 1 MiB fresh{name}"), |b| {
+      b.iter(|| drop(black_box(pool.allocate(size))))
+    });
+    c.bench_function(
+      &format!("BufPool::allocate 1 MiB fresh and touch{name}"),
+      |b| {
+        b.iter(|| {
+          let mut buf = pool.allocate_uninitialised(size);
+          buf.fill(1);
+          drop(black_box(buf))
+        })
+      },
+    );
+  }
+
+  // Allocates and drops a batch at a time, so that most drops return a slab to the pool.
+  let size = 1024;
+  let pool = BufPool::new();
+  c.bench_function("BufPool drop-heavy", |b| {
+    b.iter(|| {
+      let bufs: Vec<_> = (0..64).map(|_| pool.allocate(size)).collect();
+      drop(black_box(bufs))
+    })
+  });
+  let pool = BufPool::builder().thread_local_cache(true).build();
+  c.bench_function("BufPool drop-heavy thread_local_cache", |b| {
+    b.iter(|| {
+      let bufs: Vec<_> = (0..64).map(|_| pool.allocate(size)).collect();
+      drop(black_box(bufs))
+    })
+  });
 }
 
 criterion_group!(benches, criterion_benchmark);
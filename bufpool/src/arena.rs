@@ -0,0 +1,106 @@
+use crate::buf::Buf;
+use crate::BufPool;
+use std::mem;
+use std::panic::RefUnwindSafe;
+use std::panic::UnwindSafe;
+use std::sync::Arc;
+
+pub(crate) struct ArenaState {
+  pool: BufPool,
+  /// Every slab handed out by the arena, as (data, cap).
+  slabs: parking_lot::Mutex<Vec<(*mut u8, usize)>>,
+}
+
+unsafe impl Send for ArenaState {}
+unsafe impl Sync for ArenaState {}
+// Like the pool, nothing that can panic runs while `slabs` is locked.
+impl UnwindSafe for ArenaState {}
+impl RefUnwindSafe for ArenaState {}
+
+impl ArenaState {
+  fn release_all(&self) {
+    // Release lock before returning to the pool.
+    let slabs = mem::take(&mut *self.slabs.lock());
+    for (data, cap) in slabs {
+      self.pool.inner.release(data, cap);
+    }
+  }
+}
+
+impl Drop for ArenaState {
+  fn drop(&mut self) {
+    self.release_all();
+  }
+}
+
+/// Allocates Bufs whose slabs are all returned to the pool together, instead of one at a time as each Buf is dropped. This suits request-scoped buffers that are freed together, as dropping an arena Buf doesn't lock the pool.
+///
+/// If an arena Buf has to grow, its contents move to an ordinary pooled slab, and the original slab stays with the arena.
+pub struct Arena {
+  state: Arc<ArenaState>,
+}
+
+impl Arena {
+  pub fn new(pool: &BufPool) -> Self {
+    Self {
+      state: Arc::new(ArenaState {
+        pool: pool.clone(),
+        slabs: Default::default(),
+      }),
+    }
+  }
+
+  pub fn alloc(&self, cap: usize) -> Buf {
+    let mut buf = self.state.pool.allocate(cap);
+    self.state.slabs.lock().push((buf.data, buf.cap));
+    buf.arena = Some(self.state.clone());
+    buf
+  }
+
+  /// Returns every slab handed out so far to the pool. Panics if any Buf from this arena hasn't been dropped yet.
+  ///
+  /// Dropping the arena does the same, except that slabs are only returned once the last outstanding Buf is also dropped.
+  pub fn reset(&mut self) {
+    assert_eq!(
+      Arc::strong_count(&self.state),
+      1,
+      "arena still has Bufs in use"
+    );
+    self.state.release_all();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::arena::Arena;
+  use crate::BufPool;
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn reset_returns_every_slab() {
+    let pool = BufPool::new();
+    let mut arena = Arena::new(&pool);
+    let bufs = (0..50).map(|_| arena.alloc(64)).collect::<Vec<_>>();
+    drop(bufs);
+    // Dropping arena Bufs doesn't return their slabs.
+    assert_eq!(pool.stats().class(64).retained, 0);
+    arena.reset();
+    let stats = pool.stats();
+    assert_eq!(stats.class(64).retained, 50);
+    assert_eq!(stats.class(64).live, 0);
+
+    // The arena can be reused after a reset.
+    drop(arena.alloc(64));
+    arena.reset();
+    assert_eq!(pool.stats().class(64).retained, 50);
+  }
+
+  #[test]
+  #[should_panic(expected = "arena still has Bufs in use")]
+  fn reset_panics_with_bufs_in_use() {
+    let pool = BufPool::new();
+    let mut arena = Arena::new(&pool);
+    let _buf = arena.alloc(64);
+    arena.reset();
+  }
+}
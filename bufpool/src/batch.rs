@@ -0,0 +1,121 @@
+use crate::buf::Buf;
+use crate::BufPool;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+
+/// Returned by `BufBatch::push_slice` when the data would take the batch over its total budget. Nothing is pushed in that case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BudgetExceeded {
+  pub requested: usize,
+  pub remaining: usize,
+}
+
+impl Display for BudgetExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "pushing {} bytes exceeds remaining budget of {} bytes",
+      self.requested, self.remaining
+    )
+  }
+}
+
+impl Error for BudgetExceeded {}
+
+/// Accumulates bytes into a sequence of pooled Bufs of at most `buf_limit` bytes each, with at most `budget` bytes across all of them. Useful as a bounded write queue for a connection.
+pub struct BufBatch {
+  pool: BufPool,
+  buf_limit: usize,
+  budget: usize,
+  total_len: usize,
+  bufs: Vec<Buf>,
+}
+
+impl BufBatch {
+  pub fn new(pool: &BufPool, buf_limit: usize, budget: usize) -> Self {
+    assert!(buf_limit > 0);
+    Self {
+      pool: pool.clone(),
+      buf_limit,
+      budget,
+      total_len: 0,
+      bufs: Vec::new(),
+    }
+  }
+
+  pub fn push_slice(&mut self, data: &[u8]) -> Result<(), BudgetExceeded> {
+    let remaining = self.budget - self.total_len;
+    if data.len() > remaining {
+      return Err(BudgetExceeded {
+        requested: data.len(),
+        remaining,
+      });
+    };
+    let mut rem = data;
+    while !rem.is_empty() {
+      let buf = match self.bufs.last_mut() {
+        Some(buf) if buf.len() < self.buf_limit => buf,
+        _ => {
+          self.bufs.push(self.pool.allocate(self.buf_limit));
+          self.bufs.last_mut().unwrap()
+        }
+      };
+      let n = rem.len().min(self.buf_limit - buf.len());
+      buf.extend_from_slice(&rem[..n]);
+      rem = &rem[n..];
+    }
+    self.total_len += data.len();
+    Ok(())
+  }
+
+  /// Total bytes pushed across all Bufs.
+  pub fn total_len(&self) -> usize {
+    self.total_len
+  }
+
+  pub fn into_bufs(self) -> Vec<Buf> {
+    self.bufs
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::batch::BudgetExceeded;
+  use crate::batch::BufBatch;
+  use crate::BufPool;
+
+  #[test]
+  fn spills_into_a_new_buf_at_the_limit() {
+    let pool = BufPool::new();
+    let mut batch = BufBatch::new(&pool, 4, 100);
+    batch.push_slice(b"abc").unwrap();
+    batch.push_slice(b"defghij").unwrap();
+    assert_eq!(batch.total_len(), 10);
+    let bufs = batch.into_bufs();
+    assert_eq!(
+      bufs.iter().map(|b| &b[..]).collect::<Vec<_>>(),
+      [&b"abcd"[..], b"efgh", b"ij"]
+    );
+  }
+
+  #[test]
+  fn refuses_pushes_over_the_budget() {
+    let pool = BufPool::new();
+    let mut batch = BufBatch::new(&pool, 4, 6);
+    batch.push_slice(b"abcd").unwrap();
+    assert_eq!(
+      batch.push_slice(b"efg"),
+      Err(BudgetExceeded {
+        requested: 3,
+        remaining: 2
+      })
+    );
+    // Nothing was pushed, and what fits can still be.
+    assert_eq!(batch.total_len(), 4);
+    batch.push_slice(b"ef").unwrap();
+    assert_eq!(batch.total_len(), 6);
+    assert!(batch.push_slice(b"g").is_err());
+    assert_eq!(batch.into_bufs().len(), 2);
+  }
+}
@@ -1,21 +1,218 @@
+use crate::arena::ArenaState;
+use crate::max_try_from_len;
+use crate::shared::SharedBuf;
+use crate::AllocError;
 use crate::BufPool;
+use crate::BufPoolInner;
+use crate::BUFPOOL;
 use std::borrow::Borrow;
 use std::borrow::BorrowMut;
+use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::error::Error;
 use std::fmt;
 use std::fmt::Debug;
+use std::fmt::Display;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::io;
+use std::io::BufRead;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
+use std::mem;
+use std::mem::ManuallyDrop;
+use std::ops::Bound;
 use std::ops::Deref;
 use std::ops::DerefMut;
 use std::ops::Index;
 use std::ops::IndexMut;
+use std::ops::Range;
 use std::ops::RangeBounds;
 use std::ptr;
 use std::slice;
+use std::slice::ChunkBy;
+use std::slice::ChunksExactMut;
+use std::slice::ChunksMut;
 use std::slice::SliceIndex;
+use std::slice::Windows;
+use std::str;
+use std::str::Utf8Error;
+use std::sync::Arc;
+use std::sync::Weak;
+
+const DEBUG_PREVIEW_LEN: usize = 16;
+
+/// Returned by `Buf::try_set_len` when the requested length exceeds the capacity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LenExceedsCapacity {
+  pub len: usize,
+  pub cap: usize,
+}
+
+impl Display for LenExceedsCapacity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "length of {} bytes exceeds capacity of {} bytes",
+      self.len, self.cap
+    )
+  }
+}
+
+impl Error for LenExceedsCapacity {}
+
+/// Identifies the pool and size class of a slab taken with `Buf::into_return_token`, so that the slab can be returned with `BufPool::return_slab` by code that doesn't hold the pool. It holds only a weak reference, so it doesn't keep the pool alive.
+pub struct BufReturnToken {
+  pub(crate) pool: Weak<BufPoolInner>,
+  pub(crate) cap: usize,
+}
+
+/// An `io::Write` that appends to a borrowed Buf, growing it as necessary. Created with `Buf::tail_writer`, for passing to code that expects a `Write` while the caller keeps ownership of the Buf.
+pub struct TailWriter<'a> {
+  buf: &'a mut Buf,
+  written: usize,
+}
+
+impl<'a> TailWriter<'a> {
+  /// How many bytes have been written through this writer.
+  pub fn written(&self) -> usize {
+    self.written
+  }
+}
+
+impl<'a> Write for TailWriter<'a> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.buf.extend_from_slice(buf);
+    self.written += buf.len();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// An `io::Write` and `io::Seek` over an owned Buf, for building binary formats that need to go back and patch earlier bytes, e.g. a length prefix written as a placeholder before the body. Writes overwrite existing bytes at the position and append past the end. Seeking past the end extends the Buf with zeros up to the new position. Created with `Buf::into_cursor`.
+pub struct BufCursor {
+  buf: Buf,
+  pos: usize,
+}
+
+impl BufCursor {
+  /// Returns the Buf with everything written so far.
+  pub fn into_buf(self) -> Buf {
+    self.buf
+  }
+
+  pub fn position(&self) -> usize {
+    self.pos
+  }
+}
+
+impl Write for BufCursor {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = buf.len().min(self.buf.len() - self.pos);
+    self.buf[self.pos..self.pos + n].copy_from_slice(&buf[..n]);
+    self.buf.extend_from_slice(&buf[n..]);
+    self.pos += buf.len();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl Seek for BufCursor {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(n) => (0, i64::try_from(n).ok()),
+      SeekFrom::End(n) => (self.buf.len(), Some(n)),
+      SeekFrom::Current(n) => (self.pos, Some(n)),
+    };
+    let new = offset
+      .and_then(|o| {
+        if o >= 0 {
+          usize::try_from(o).ok().and_then(|o| base.checked_add(o))
+        } else {
+          usize::try_from(o.unsigned_abs())
+            .ok()
+            .and_then(|o| base.checked_sub(o))
+        }
+      })
+      .ok_or_else(|| {
+        io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "invalid seek to a negative or overflowing position",
+        )
+      })?;
+    if new > self.buf.len() {
+      self.buf.set_len_filled(new);
+    };
+    self.pos = new;
+    Ok(new as u64)
+  }
+}
+
+/// Rolls a borrowed Buf back to its length at creation when dropped, unless `commit` is called first. This allows appending speculatively, e.g. while parsing input that may turn out to be invalid, and returning early without leaving partial output behind. Only appended bytes are rolled back; bytes before the original length that were modified or truncated stay that way. Created with `Buf::guard`; the Buf is accessible through `Deref`.
+pub struct BufGuard<'a> {
+  buf: &'a mut Buf,
+  committed_len: usize,
+  committed: bool,
+}
+
+impl<'a> BufGuard<'a> {
+  /// Keeps everything appended through this guard.
+  pub fn commit(mut self) {
+    self.committed = true;
+  }
+}
+
+impl<'a> Deref for BufGuard<'a> {
+  type Target = Buf;
+
+  fn deref(&self) -> &Self::Target {
+    self.buf
+  }
+}
+
+impl<'a> DerefMut for BufGuard<'a> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    self.buf
+  }
+}
+
+impl<'a> Drop for BufGuard<'a> {
+  fn drop(&mut self) {
+    if !self.committed {
+      self.buf.truncate(self.committed_len);
+    };
+  }
+}
+
+/// Resolves `range` against a slice of length `len`, panicking like slice indexing would if it's out of bounds.
+fn resolve_range(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
+  let start = match range.start_bound() {
+    Bound::Included(&i) => i,
+    Bound::Excluded(&i) => i.checked_add(1).expect("range start overflow"),
+    Bound::Unbounded => 0,
+  };
+  let end = match range.end_bound() {
+    Bound::Included(&i) => i.checked_add(1).expect("range end overflow"),
+    Bound::Excluded(&i) => i,
+    Bound::Unbounded => len,
+  };
+  assert!(
+    start <= end,
+    "range start {start} is greater than end {end}"
+  );
+  assert!(
+    end <= len,
+    "range end {end} is out of bounds for length {len}"
+  );
+  start..end
+}
 
 // We could've made this simpler instead of trying to copy Vec<u8>, but:
 // - It would expose uninitialised data, unless we zero-fill every allocation (whether new or from the pool).
@@ -25,31 +222,73 @@ pub struct Buf {
   pub(crate) len: usize,
   pub(crate) cap: usize,
   pub(crate) pool: BufPool,
+  /// Set if the slab belongs to an `Arena`, which returns it to the pool instead of this Buf.
+  pub(crate) arena: Option<Arc<ArenaState>>,
 }
 
 unsafe impl Send for Buf {}
 unsafe impl Sync for Buf {}
 
 // Not implemented:
-// - `from_raw_parts*, into_*, leak, new*, reserve*, resize*, shrink_to*, try_reserve*, with_capacity*`: not applicable.
-// - `as_mut_ptr, as_ptr, is_empty, len`: already available on `Deref/DerefMut`.
-// - `insert, remove, retain*, swap_remove`: unlikely to be used.
-// - `dedup*, drain*, spare_capacity_*, splice, split_*`: complex, may implement if required.
+// - `from_raw_parts_in, into_boxed_slice, into_flattened, leak, new*, resize*, shrink_to*, try_reserve*, with_capacity*`: not applicable.
+// - `as_mut_ptr, as_ptr`: already available on `Deref/DerefMut`.
+// - `insert, remove, retain, swap_remove`: unlikely to be used.
+// - `dedup*, drain*, spare_capacity_*, split_*`: complex, may implement if required.
 impl Buf {
   fn _as_full_slice(&mut self) -> &mut [u8] {
     unsafe { slice::from_raw_parts_mut(self.data, self.cap) }
   }
 
+  /// Adler-32 checksum of the contents.
+  #[cfg(feature = "checksum")]
+  pub fn adler32(&self) -> u32 {
+    adler::adler32_slice(self.as_slice())
+  }
+
   pub fn allocator(&self) -> &BufPool {
     &self.pool
   }
 
+  /// The alignment of the pool this Buf was allocated from.
+  pub fn alignment(&self) -> usize {
+    self.pool.inner.align
+  }
+
   pub fn append(&mut self, other: &mut Buf) {
-    // SAFETY: This will panic if out of bounds.
     self.extend_from_slice(other.as_slice());
     other.clear();
   }
 
+  /// Views the contents as `N`-byte arrays, e.g. cipher blocks, plus the remainder that doesn't fill a whole array.
+  pub fn as_chunks<const N: usize>(&self) -> (&[[u8; N]], &[u8]) {
+    self.as_slice().as_chunks()
+  }
+
+  pub fn as_chunks_mut<const N: usize>(&mut self) -> (&mut [[u8; N]], &mut [u8]) {
+    self.as_mut_slice().as_chunks_mut()
+  }
+
+  /// Returns the memfd backing this buffer's slab, if it's from a memfd-backed pool. The buffer's contents are at offset zero of the file.
+  #[cfg(target_os = "linux")]
+  pub fn as_fd(&self) -> Option<std::os::fd::BorrowedFd<'_>> {
+    use std::os::fd::AsRawFd;
+
+    let raw = self
+      .pool
+      .inner
+      .memfds
+      .lock()
+      .get(&(self.data as usize))?
+      .as_raw_fd();
+    // SAFETY: The memfd is only closed when the slab is freed, which can't happen while this Buf exists.
+    Some(unsafe { std::os::fd::BorrowedFd::borrow_raw(raw) })
+  }
+
+  /// Views the contents as UTF-8 text, failing if they aren't valid UTF-8.
+  pub fn as_str(&self) -> Result<&str, Utf8Error> {
+    str::from_utf8(self.as_slice())
+  }
+
   pub fn as_slice(&self) -> &[u8] {
     unsafe { slice::from_raw_parts(self.data, self.len) }
   }
@@ -58,29 +297,266 @@ impl Buf {
     unsafe { slice::from_raw_parts_mut(self.data, self.len) }
   }
 
+  pub fn binary_search(&self, x: &u8) -> Result<usize, usize> {
+    self.as_slice().binary_search(x)
+  }
+
   pub fn capacity(&self) -> usize {
     self.cap
   }
 
+  /// Iterates over runs of adjacent bytes for which `pred` holds between each consecutive pair, e.g. `|a, b| a == b` for runs of equal bytes when run-length encoding.
+  pub fn chunk_by<F: FnMut(&u8, &u8) -> bool>(&self, pred: F) -> ChunkBy<'_, u8, F> {
+    self.as_slice().chunk_by(pred)
+  }
+
+  /// Iterates over the contents in mutable blocks of `size` bytes; the last block may be shorter.
+  pub fn chunks_mut(&mut self, size: usize) -> ChunksMut<'_, u8> {
+    self.as_mut_slice().chunks_mut(size)
+  }
+
+  /// Iterates over the contents in mutable blocks of exactly `size` bytes; any remainder is available from `into_remainder` on the iterator.
+  pub fn chunks_exact_mut(&mut self, size: usize) -> ChunksExactMut<'_, u8> {
+    self.as_mut_slice().chunks_exact_mut(size)
+  }
+
   pub fn clear(&mut self) {
     self.len = 0;
   }
 
+  /// Reduces each run of consecutive `byte`s to a single one in place, e.g. to collapse runs of spaces in a header. Other bytes are left alone.
+  pub fn collapse_runs(&mut self, byte: u8) {
+    let slice = self.as_mut_slice();
+    let mut w = 0;
+    for r in 0..slice.len() {
+      let b = slice[r];
+      if b == byte && w > 0 && slice[w - 1] == byte {
+        continue;
+      };
+      slice[w] = b;
+      w += 1;
+    }
+    self.len = w;
+  }
+
+  /// Moves the contents to a smaller slab if they use less than a quarter of the capacity, returning the current slab to the pool. This is a cheap way to tidy up long-lived buffers after heavy shrinking, without churning slabs for minor shrinkage. An empty Buf gives up its slab entirely, as if allocated with `allocate(0)`.
+  pub fn compact(&mut self) {
+    if self.len >= self.cap / 4 {
+      return;
+    };
+    let mut new = self.pool.allocate_replacement(self.len);
+    new.extend_from_slice(self.as_slice());
+    mem::swap(self, &mut new);
+  }
+
+  /// Replaces the contents with a copy of `src`'s, reusing the current slab if it's large enough, e.g. to recycle a scratch buffer.
+  pub fn copy_from_buf(&mut self, src: &Buf) {
+    self.clear();
+    self.extend_from_slice(src.as_slice());
+  }
+
+  /// Copies the contents into the start of `dst`. Panics if `dst` is shorter than the contents.
+  pub fn copy_to_slice(&self, dst: &mut [u8]) {
+    assert!(
+      dst.len() >= self.len,
+      "destination of {} bytes is shorter than length {}",
+      dst.len(),
+      self.len
+    );
+    dst[..self.len].copy_from_slice(self.as_slice());
+  }
+
+  /// Copies as much of the contents as fits into the start of `dst`, and returns how many bytes were copied.
+  pub fn copy_to_slice_truncating(&self, dst: &mut [u8]) -> usize {
+    let n = self.len.min(dst.len());
+    dst[..n].copy_from_slice(&self.as_slice()[..n]);
+    n
+  }
+
+  /// CRC-32 (IEEE) checksum of the contents.
+  #[cfg(feature = "checksum")]
+  pub fn crc32(&self) -> u32 {
+    crc32fast::hash(self.as_slice())
+  }
+
+  /// Compares the contents with `other` byte by byte, without the pointer identity fast path used by `==`.
+  pub fn eq_bytes(&self, other: &[u8]) -> bool {
+    self.as_slice() == other
+  }
+
+  /// Grows the buffer using `reserve` if `other` doesn't fit in the remaining capacity.
   pub fn extend_from_slice(&mut self, other: &[u8]) {
+    self.reserve(other.len());
     let idx = self.len;
-    // SAFETY: This will panic if out of bounds.
     self._as_full_slice()[idx..idx + other.len()].copy_from_slice(other);
     self.len += other.len();
   }
 
+  /// Appends each slice in order, reserving the total length once up front.
+  pub fn extend_from_slices(&mut self, slices: &[&[u8]]) {
+    let total = slices
+      .iter()
+      .try_fold(0usize, |total, s| total.checked_add(s.len()))
+      .expect("capacity overflow");
+    self.reserve(total);
+    for s in slices {
+      let idx = self.len;
+      self._as_full_slice()[idx..idx + s.len()].copy_from_slice(s);
+      self.len += s.len();
+    }
+  }
+
+  /// Appends a copy of the bytes in `src`, which must be within the current length. The buffer is grown using `reserve` if necessary.
   pub fn extend_from_within(&mut self, src: impl RangeBounds<usize>) {
+    let Range { start, end } = resolve_range(src, self.len);
+    self.reserve(end - start);
     let idx = self.len;
-    // SAFETY: This will panic if out of bounds.
-    self._as_full_slice().copy_within(src, idx);
+    self._as_full_slice().copy_within(start..end, idx);
+    self.len += end - start;
+  }
+
+  /// Appends `additional` zero bytes with a single memset, growing the buffer using `reserve` if necessary.
+  pub fn extend_zeroed(&mut self, additional: usize) {
+    self.reserve(additional);
+    unsafe { ptr::write_bytes(self.data.add(self.len), 0, additional) };
+    self.len += additional;
+  }
+
+  /// Converts the Buf into a SharedBuf viewing only the bytes in `range`, e.g. to hand out a frame's body after parsing its header. The whole slab is returned to the pool once the last clone of the view is dropped. Panics if `range` is out of bounds.
+  pub fn freeze_range(self, range: impl RangeBounds<usize>) -> SharedBuf {
+    let range = resolve_range(range, self.len);
+    SharedBuf {
+      buf: Arc::new(self),
+      range,
+    }
+  }
+
+  /// Reassembles a Buf from the parts returned by `into_raw_parts`, e.g. after they've crossed an FFI boundary. The slab will be returned to `pool` when the Buf is dropped.
+  ///
+  /// # Safety
+  ///
+  /// - `data` must point to a slab of `cap` bytes that can be returned to `pool`: ideally it came from `into_raw_parts` on a Buf from the same pool, but at least it must have been allocated by a pool with the same alignment and backing (for a heap-backed pool, this means the global allocator with `Layout::from_size_align(cap, pool_alignment)`).
+  /// - `cap` must be a power of two, or zero if the parts came from a zero-capacity Buf (in which case `data` is never dereferenced or freed).
+  /// - `len` must not exceed `cap`, and the first `len` bytes must be initialised.
+  /// - Nothing else may use or free the slab afterwards.
+  pub unsafe fn from_raw_parts(pool: &BufPool, data: *mut u8, len: usize, cap: usize) -> Buf {
+    debug_assert!(!data.is_null());
+    debug_assert!(cap == 0 || cap.is_power_of_two());
+    debug_assert!(len <= cap);
+    Buf {
+      data,
+      len,
+      cap,
+      pool: pool.clone(),
+      arena: None,
+    }
+  }
+
+  /// Hands the slab to a `bytes::Bytes` without copying. The slab is returned to the pool once the `Bytes` and all clones and slices of it have been dropped.
+  #[cfg(feature = "bytes")]
+  pub fn into_bytes(self) -> bytes::Bytes {
+    bytes::Bytes::from_owner(self)
+  }
+
+  /// Wraps the Buf in a `BufCursor` positioned at the start, for writing and seeking back to patch earlier bytes. Existing contents are kept and overwritten by writes.
+  pub fn into_cursor(self) -> BufCursor {
+    BufCursor { buf: self, pos: 0 }
+  }
+
+  /// Copies the contents into a FixedBuf from `pool`, e.g. to hand a completed buffer to an `O_DIRECT` write. The capacity is rounded up to the next power of two, and the bytes after the contents are zeroed. As the two are different pools, this always copies, and this Buf's slab is returned to its own pool.
+  #[cfg(feature = "fixed")]
+  pub fn into_fixed(self, pool: &bufpool_fixed::FixedBufPool) -> bufpool_fixed::buf::FixedBuf {
+    let mut fixed = pool.allocate_with_zeros(self.len.next_power_of_two());
+    fixed[..self.len].copy_from_slice(self.as_slice());
+    // Slabs reused by the fixed pool aren't zeroed.
+    fixed.zero_range(self.len..);
+    fixed
+  }
+
+  /// Inserts `slice` at `index`, shifting the bytes after it and growing the buffer using `reserve` if necessary. Panics if `index` is greater than the length.
+  pub fn insert_slice(&mut self, index: usize, slice: &[u8]) {
+    assert!(
+      index <= self.len,
+      "insertion index {index} is out of bounds for length {}",
+      self.len
+    );
+    self.splice(index..index, slice);
+  }
+
+  /// Whether the data pointer is aligned to `align`, e.g. to check that the buffer is suitable for `O_DIRECT` before issuing a syscall. `align` must be a power of two.
+  pub fn is_aligned_to(&self, align: usize) -> bool {
+    assert!(align.is_power_of_two());
+    self.data as usize & (align - 1) == 0
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Whether the length has reached the capacity, so that appending anything will cause the buffer to grow.
+  pub fn is_full(&self) -> bool {
+    self.len == self.cap
+  }
+
+  /// Iterates over `(index, byte)` pairs.
+  pub fn iter_indexed(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+    self.as_slice().iter().copied().enumerate()
+  }
+
+  /// Iterates over `(index, &mut byte)` pairs, e.g. for position-dependent transforms like masking.
+  pub fn iter_mut_indexed(&mut self) -> impl Iterator<Item = (usize, &mut u8)> + '_ {
+    self.as_mut_slice().iter_mut().enumerate()
+  }
+
+  /// Decomposes the Buf into its data pointer, length, and capacity, without returning the slab to the pool. The slab is leaked unless it's passed back to `from_raw_parts`.
+  ///
+  /// Panics if the Buf was allocated from an `Arena`, as the arena still owns the slab.
+  pub fn into_raw_parts(self) -> (*mut u8, usize, usize) {
+    assert!(
+      self.arena.is_none(),
+      "cannot take raw parts of an arena Buf"
+    );
+    let buf = ManuallyDrop::new(self);
+    // Drop our reference to the pool, which ManuallyDrop would otherwise leak.
+    drop(unsafe { ptr::read(&buf.pool) });
+    (buf.data, buf.len, buf.cap)
+  }
+
+  /// Like `into_raw_parts`, but returns a token to pass back to `BufPool::return_slab` along with the data pointer, instead of the pool having to be kept around.
+  ///
+  /// Panics if the Buf was allocated from an `Arena`, as the arena still owns the slab.
+  pub fn into_return_token(self) -> (BufReturnToken, *mut u8) {
+    let pool = Arc::downgrade(&self.pool.inner);
+    let (data, _, cap) = self.into_raw_parts();
+    (BufReturnToken { pool, cap }, data)
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Replaces each byte with `f(byte)`, e.g. to apply a WebSocket mask or fold case.
+  pub fn map_in_place(&mut self, mut f: impl FnMut(u8) -> u8) {
+    for b in self.as_mut_slice() {
+      *b = f(*b);
+    }
+  }
+
+  pub fn partition_point(&self, pred: impl FnMut(&u8) -> bool) -> usize {
+    self.as_slice().partition_point(pred)
+  }
+
+  /// Moves the contents of `other` to the front of this buffer, leaving `other` empty. If this buffer is empty, the slabs are swapped instead of copying.
+  pub fn prepend(&mut self, other: &mut Buf) {
+    if self.is_empty() {
+      self.swap_with(other);
+      return;
+    };
+    self.splice(0..0, other.as_slice());
+    other.clear();
   }
 
   pub fn push(&mut self, v: u8) {
-    // SAFETY: This will panic if out of bounds.
     self.extend_from_slice(&[v]);
   }
 
@@ -93,17 +569,270 @@ impl Buf {
     Some(self._as_full_slice()[idx])
   }
 
+  /// Returns the capacity that `reserve(additional)` would result in. If the capacity has to grow, it at least doubles (to the next power of two that fits), so that appending one byte at a time only reallocates a logarithmic number of times, like `Vec`. It only grows by as much as needed if doubling would exceed the pool's `max_alloc`.
+  pub fn grown_capacity(&self, additional: usize) -> usize {
+    let required = self.len.checked_add(additional).expect("capacity overflow");
+    if required <= self.cap {
+      return self.cap;
+    };
+    let doubled = required
+      .max(self.cap * 2)
+      .checked_next_power_of_two()
+      .expect("capacity overflow");
+    // Don't let doubling push a growth that's within the pool's `max_alloc` over it.
+    if doubled > self.pool.inner.max_alloc {
+      return required
+        .checked_next_power_of_two()
+        .expect("capacity overflow");
+    };
+    doubled
+  }
+
+  /// Returns a `BufGuard` that truncates the Buf back to its current length when dropped, unless committed.
+  pub fn guard(&mut self) -> BufGuard<'_> {
+    BufGuard {
+      committed_len: self.len,
+      buf: self,
+      committed: false,
+    }
+  }
+
+  /// Appends bytes from `reader` up to and including `delim`, or until EOF, growing as necessary, like `BufRead::read_until` but into a pooled buffer. Returns how many bytes were appended.
+  pub fn read_until_from(&mut self, reader: &mut impl BufRead, delim: u8) -> io::Result<usize> {
+    let mut read = 0;
+    loop {
+      let available = match reader.fill_buf() {
+        Ok(available) => available,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => return Err(e),
+      };
+      let (done, used) = match memchr::memchr(delim, available) {
+        Some(i) => (true, i + 1),
+        None => (available.is_empty(), available.len()),
+      };
+      self.extend_from_slice(&available[..used]);
+      reader.consume(used);
+      read += used;
+      if done {
+        return Ok(read);
+      };
+    }
+  }
+
+  /// Ensures the capacity is at least `len() + additional`. If it isn't, the contents are moved to a larger slab from the same pool (see `grown_capacity`), and the current slab is returned to the pool.
+  pub fn reserve(&mut self, additional: usize) {
+    let cap = self.grown_capacity(additional);
+    if cap == self.cap {
+      return;
+    };
+    // `max_alloc` limits the bytes requested, which may round up to a class above it.
+    let requested = if cap > self.pool.inner.max_alloc {
+      self.len + additional
+    } else {
+      cap
+    };
+    let mut new = self.pool.allocate_replacement(requested);
+    new.extend_from_slice(self.as_slice());
+    mem::swap(self, &mut new);
+  }
+
+  /// Keeps only the bytes for which `f` returns true, in order, in a single pass. Like `Vec::retain_mut`, `f` can also modify the bytes it keeps, e.g. to lowercase letters while dropping everything else.
+  pub fn retain_mut(&mut self, mut f: impl FnMut(&mut u8) -> bool) {
+    let slice = self.as_mut_slice();
+    let mut w = 0;
+    for r in 0..slice.len() {
+      let mut b = slice[r];
+      if f(&mut b) {
+        slice[w] = b;
+        w += 1;
+      };
+    }
+    self.len = w;
+  }
+
+  pub fn reverse(&mut self) {
+    self.as_mut_slice().reverse();
+  }
+
+  /// Returns the index of the last occurrence of `needle`.
+  pub fn rfind(&self, needle: u8) -> Option<usize> {
+    memchr::memrchr(needle, self.as_slice())
+  }
+
+  pub fn rotate_left(&mut self, mid: usize) {
+    self.as_mut_slice().rotate_left(mid);
+  }
+
+  pub fn rotate_right(&mut self, k: usize) {
+    self.as_mut_slice().rotate_right(k);
+  }
+
+  /// Iterates over the subslices separated by `delim`, starting from the end. Like `slice::rsplit`, an empty subslice is yielded for a leading or trailing `delim`.
+  pub fn rsplit_on(&self, delim: u8) -> impl Iterator<Item = &[u8]> {
+    self.as_slice().rsplit(move |b| *b == delim)
+  }
+
+  /// # Safety
+  ///
+  /// The bytes up to `len` must be initialised before they are read.
   pub unsafe fn set_len(&mut self, len: usize) {
     assert!(len <= self.cap);
     self.len = len;
   }
 
+  /// A safe alternative to `set_len`: growing zeroes the newly exposed bytes (and grows the capacity using `reserve` if necessary), and shrinking is the same as `truncate`.
+  pub fn set_len_filled(&mut self, new_len: usize) {
+    if new_len <= self.len {
+      self.truncate(new_len);
+      return;
+    };
+    self.reserve(new_len - self.len);
+    let len = self.len;
+    self._as_full_slice()[len..new_len].fill(0);
+    self.len = new_len;
+  }
+
+  /// Like `set_len`, but clamps `len` to the capacity instead of panicking.
+  ///
+  /// # Safety
+  ///
+  /// The bytes up to the resulting length must be initialised before they are read.
+  pub unsafe fn set_len_saturating(&mut self, len: usize) {
+    self.len = len.min(self.cap);
+  }
+
+  /// Copies the contents into the spare capacity of each destination in order, without growing any of them, e.g. to fan out into pre-sized shards. Returns how many bytes were copied, and the index of the first destination that still has spare capacity (or `dests.len()` if none do), from which a later scatter can resume.
+  pub fn scatter_into(&self, dests: &mut [Buf]) -> (usize, usize) {
+    let mut rem = self.as_slice();
+    let mut i = 0;
+    while i < dests.len() {
+      let n = rem.len().min(dests[i].spare_capacity_len());
+      dests[i].extend_from_slice(&rem[..n]);
+      rem = &rem[n..];
+      if !dests[i].is_full() {
+        break;
+      };
+      i += 1;
+    }
+    (self.len - rem.len(), i)
+  }
+
+  /// Discards the first `n` bytes by moving the rest to the front, e.g. once a parsed prefix of a socket buffer has been consumed. Panics if `n` is greater than the length.
+  pub fn shift_left(&mut self, n: usize) {
+    assert!(
+      n <= self.len,
+      "shift of {n} is out of bounds for length {}",
+      self.len
+    );
+    let len = self.len;
+    self.as_mut_slice().copy_within(n..len, 0);
+    self.len -= n;
+  }
+
+  /// Copies the bytes in `range` into a new Buf from the same pool, leaving this one unchanged. Panics if `range` is out of bounds.
+  pub fn slice_to_owned(&self, range: impl RangeBounds<usize>) -> Buf {
+    let range = resolve_range(range, self.len);
+    self.pool.allocate_from_data(&self.as_slice()[range])
+  }
+
+  /// How many more bytes can be appended before the buffer has to grow.
+  pub fn spare_capacity_len(&self) -> usize {
+    self.cap - self.len
+  }
+
+  /// Replaces the bytes in `range` with `replacement`, shifting the bytes after the range as necessary. The buffer is grown using `reserve` if `replacement` is longer than the range.
+  pub fn splice(&mut self, range: impl RangeBounds<usize>, replacement: &[u8]) {
+    let Range { start, end } = resolve_range(range, self.len);
+    if replacement.len() > end - start {
+      self.reserve(replacement.len() - (end - start));
+    };
+    let len = self.len;
+    let new_end = start + replacement.len();
+    let full = self._as_full_slice();
+    full.copy_within(end..len, new_end);
+    full[start..new_end].copy_from_slice(replacement);
+    self.len = new_end + (len - end);
+  }
+
+  /// Exchanges the contents of the two buffers in O(1) by swapping their slabs. If they're from different pools, the pools are swapped too, so each slab still returns to the pool it came from.
+  ///
+  /// This isn't named `swap` as that would shadow `<[u8]>::swap`, which swaps two bytes.
+  pub fn swap_with(&mut self, other: &mut Buf) {
+    mem::swap(self, other);
+  }
+
+  /// Returns an `io::Write` that appends to this Buf. Everything written is already in the Buf once the writer is dropped.
+  pub fn tail_writer(&mut self) -> TailWriter<'_> {
+    TailWriter {
+      buf: self,
+      written: 0,
+    }
+  }
+
+  /// Standard base64 (with padding) encoding of the contents, in a new Buf from the same pool.
+  #[cfg(feature = "base64")]
+  pub fn to_base64(&self) -> Buf {
+    use base64::Engine;
+    let len = base64::encoded_len(self.len, true).expect("encoded length overflows usize");
+    let mut out = self.pool.allocate_uninitialised(len);
+    let n = base64::engine::general_purpose::STANDARD
+      .encode_slice(self.as_slice(), out.as_mut_slice())
+      .unwrap();
+    debug_assert_eq!(n, len);
+    out
+  }
+
+  /// Views the contents as UTF-8 text, replacing invalid sequences with U+FFFD (which requires a copy).
+  pub fn to_string_lossy(&self) -> Cow<'_, str> {
+    String::from_utf8_lossy(self.as_slice())
+  }
+
   pub fn truncate(&mut self, len: usize) {
     if len >= self.len {
       return;
     };
     self.len = len;
   }
+
+  /// Like `clone`, but returns an error instead of panicking if the pool can't allocate.
+  pub fn try_clone(&self) -> Result<Buf, AllocError> {
+    let mut clone = self.pool.try_allocate(self.len)?;
+    clone.extend_from_slice(self.as_slice());
+    Ok(clone)
+  }
+
+  /// Like `set_len`, but returns an error instead of panicking if `len` exceeds the capacity.
+  ///
+  /// # Safety
+  ///
+  /// The bytes up to `len` must be initialised before they are read.
+  pub unsafe fn try_set_len(&mut self, len: usize) -> Result<(), LenExceedsCapacity> {
+    if len > self.cap {
+      return Err(LenExceedsCapacity { len, cap: self.cap });
+    };
+    self.len = len;
+    Ok(())
+  }
+
+  /// Iterates over every overlapping window of `size` bytes, e.g. for rolling hashes. Yields nothing if `size` exceeds the length, and panics if `size` is zero.
+  pub fn windows(&self, size: usize) -> Windows<'_, u8> {
+    self.as_slice().windows(size)
+  }
+
+  /// Provides the spare capacity to `f` as a `BorrowedCursor`, e.g. for `Read::read_buf`, so that it can be filled without being zeroed first. Whatever `f` writes to the cursor is appended.
+  #[cfg(feature = "read-buf")]
+  pub fn with_unfilled<R>(&mut self, f: impl FnOnce(std::io::BorrowedCursor<'_>) -> R) -> R {
+    let spare = unsafe {
+      slice::from_raw_parts_mut(
+        self.data.add(self.len).cast::<std::mem::MaybeUninit<u8>>(),
+        self.cap - self.len,
+      )
+    };
+    let mut borrowed = std::io::BorrowedBuf::from(spare);
+    let res = f(borrowed.unfilled());
+    self.len += borrowed.len();
+    res
+  }
 }
 
 impl AsRef<[u8]> for Buf {
@@ -139,11 +868,25 @@ impl Clone for Buf {
   }
 }
 
+/// Only the first `DEBUG_PREVIEW_LEN` bytes are shown by `{:?}`, so that logging a large buffer doesn't dump megabytes of data. Use `{:#?}` to print the full contents.
 impl Debug for Buf {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    f.debug_struct("Buf")
-      .field("data", &self.as_slice())
-      .finish()
+    let alternate = f.alternate();
+    let mut s = f.debug_struct("Buf");
+    s.field("len", &self.len).field("cap", &self.cap);
+    if alternate {
+      s.field("data", &self.as_slice());
+    } else {
+      let data = self.as_slice();
+      let preview = &data[..data.len().min(DEBUG_PREVIEW_LEN)];
+      let more = if data.len() > DEBUG_PREVIEW_LEN {
+        "…"
+      } else {
+        ""
+      };
+      s.field("data", &format_args!("{:02x?}{}", preview, more));
+    };
+    s.finish()
   }
 }
 
@@ -163,16 +906,10 @@ impl DerefMut for Buf {
 
 impl Drop for Buf {
   fn drop(&mut self) {
-    #[cfg(not(feature = "no-pool"))]
-    self.pool.inner.sizes[self.capacity().ilog2() as usize]
-      .0
-      .lock()
-      .push_back(self.data);
-    #[cfg(feature = "no-pool")]
-    unsafe {
-      let layout = std::alloc::Layout::from_size_align(self.cap, self.pool.inner.align).unwrap();
-      std::alloc::dealloc(self.data, layout)
-    }
+    // Arena slabs are returned all at once by the arena.
+    if self.arena.is_none() {
+      self.pool.inner.release(self.data, self.cap);
+    };
   }
 }
 
@@ -180,6 +917,8 @@ impl Eq for Buf {}
 
 impl<'a> Extend<&'a u8> for Buf {
   fn extend<T: IntoIterator<Item = &'a u8>>(&mut self, iter: T) {
+    let iter = iter.into_iter();
+    self.reserve(iter.size_hint().0);
     for b in iter {
       self.push(*b);
     }
@@ -188,12 +927,21 @@ impl<'a> Extend<&'a u8> for Buf {
 
 impl Extend<u8> for Buf {
   fn extend<T: IntoIterator<Item = u8>>(&mut self, iter: T) {
+    let iter = iter.into_iter();
+    self.reserve(iter.size_hint().0);
     for b in iter {
       self.push(b);
     }
   }
 }
 
+#[cfg(feature = "bytes")]
+impl From<Buf> for bytes::Bytes {
+  fn from(buf: Buf) -> Self {
+    buf.into_bytes()
+  }
+}
+
 impl Hash for Buf {
   fn hash<H: Hasher>(&self, state: &mut H) {
     self.as_slice().hash(state);
@@ -222,18 +970,47 @@ impl Ord for Buf {
   }
 }
 
+/// Lengths are compared first, so the pointer identity fast path only ever applies to Bufs of the same length.
 impl PartialEq for Buf {
   fn eq(&self, other: &Self) -> bool {
     self.len == other.len && (ptr::eq(self.data, other.data) || self.as_slice() == other.as_slice())
   }
 }
 
+/// A FixedBuf's contents are its entire capacity, so it's only equal to a Buf whose length matches that capacity.
+#[cfg(feature = "fixed")]
+impl PartialEq<bufpool_fixed::buf::FixedBuf> for Buf {
+  fn eq(&self, other: &bufpool_fixed::buf::FixedBuf) -> bool {
+    self.as_slice() == other.as_slice()
+  }
+}
+
+#[cfg(feature = "fixed")]
+impl PartialEq<Buf> for bufpool_fixed::buf::FixedBuf {
+  fn eq(&self, other: &Buf) -> bool {
+    other == self
+  }
+}
+
+// Kept as the slice comparison, which is equivalent to `Ord::cmp`.
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for Buf {
   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
     self.as_slice().partial_cmp(other.as_slice())
   }
 }
 
+/// Copies the slice into a Buf from `BUFPOOL`, failing if it's longer than the limit set by `set_max_try_from_len`.
+impl TryFrom<&[u8]> for Buf {
+  type Error = AllocError;
+
+  fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+    let mut buf = BUFPOOL.allocate_bounded(data.len(), max_try_from_len())?;
+    buf.extend_from_slice(data);
+    Ok(buf)
+  }
+}
+
 impl Write for Buf {
   fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
     self.extend_from_slice(buf);
@@ -244,3 +1021,1122 @@ impl Write for Buf {
     Ok(())
   }
 }
+
+/// Appends the UTF-8 bytes of formatted text, e.g. via `write!`, without going through an intermediate `String`.
+impl fmt::Write for Buf {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.extend_from_slice(s.as_bytes());
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::buf::Buf;
+  use crate::buf::LenExceedsCapacity;
+  use crate::AllocError;
+  use crate::BufPool;
+
+  #[test]
+  fn try_clone_returns_error_when_pool_refuses() {
+    // The pool refuses a second Buf, standing in for the allocator failing.
+    let pool = BufPool::with_max_live(1);
+    let buf = pool.allocate_from_data(b"hello");
+    assert_eq!(
+      buf.try_clone().unwrap_err(),
+      AllocError::LimitReached { max: 1 }
+    );
+    drop(buf);
+    let buf = pool.allocate_from_data(b"hello");
+    let pool = BufPool::new();
+    let other = pool.allocate_from_data(buf.as_slice()).try_clone().unwrap();
+    assert_eq!(other, buf);
+  }
+
+  #[test]
+  fn debug_previews_unless_alternate() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(32);
+    buf.extend_from_slice(&[0xab; 20]);
+    assert_eq!(
+      format!("{:?}", buf),
+      "Buf { len: 20, cap: 32, data: [ab, ab, ab, ab, ab, ab, ab, ab, ab, ab, ab, ab, ab, ab, ab, ab]… }"
+    );
+    let full = format!("{:#?}", buf);
+    assert!(full.contains("len: 20"));
+    assert_eq!(full.matches("171,").count(), 20);
+
+    buf.truncate(2);
+    assert_eq!(
+      format!("{:?}", buf),
+      "Buf { len: 2, cap: 32, data: [ab, ab] }"
+    );
+  }
+
+  #[test]
+  fn push_reallocates_logarithmically() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(1);
+    for i in 0..1_000_000 {
+      buf.push(i as u8);
+    }
+    assert_eq!(buf.len(), 1_000_000);
+    let hist = pool.size_histogram();
+    // Each reallocation doubles the capacity, so no class is requested twice, and there's at most one per power of two up to 2^20.
+    assert!(hist.iter().all(|&n| n <= 1));
+    assert!(hist.iter().sum::<u64>() <= 21);
+  }
+
+  #[test]
+  fn splice_handles_each_length() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"Host: a.com\r\n");
+
+    // Equal length.
+    buf.splice(6..11, b"b.org");
+    assert_eq!(&buf[..], b"Host: b.org\r\n");
+    // Shorter.
+    buf.splice(6..11, b"c.io");
+    assert_eq!(&buf[..], b"Host: c.io\r\n");
+    // Longer, growing past the capacity.
+    buf.splice(6..10, b"example.com");
+    assert_eq!(&buf[..], b"Host: example.com\r\n");
+    assert!(buf.capacity() >= buf.len());
+    // At the very end.
+    let len = buf.len();
+    buf.splice(len.., b"Accept: */*\r\n");
+    assert_eq!(&buf[..], b"Host: example.com\r\nAccept: */*\r\n");
+    buf.splice(19.., b"");
+    assert_eq!(&buf[..], b"Host: example.com\r\n");
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn raw_parts_round_trip() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(100);
+    buf.extend_from_slice(b"across the boundary");
+    let (data, len, cap) = buf.into_raw_parts();
+    assert_eq!((len, cap), (19, 128));
+    // Still counted as live, as the slab hasn't been returned.
+    assert_eq!(pool.stats().class(128).live, 1);
+
+    let buf = unsafe { Buf::from_raw_parts(&pool, data, len, cap) };
+    assert_eq!(&buf[..], b"across the boundary");
+    assert_eq!(buf.capacity(), 128);
+    drop(buf);
+    let stats = pool.stats();
+    assert_eq!(stats.class(128).live, 0);
+    assert_eq!(stats.class(128).retained, 1);
+  }
+
+  #[cfg(feature = "bytes")]
+  #[test]
+  fn into_bytes_returns_slab_after_all_clones_drop() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(64);
+    buf.extend_from_slice(b"zero copy");
+    let ptr = buf.as_ptr();
+    let bytes = buf.into_bytes();
+    assert_eq!(bytes.as_ptr(), ptr);
+    let clone = bytes.clone();
+    let slice = bytes.slice(5..);
+    assert_eq!(&slice[..], b"copy");
+
+    drop(bytes);
+    drop(clone);
+    assert_eq!(pool.stats().class(64).retained, 0);
+    drop(slice);
+    let stats = pool.stats();
+    assert_eq!(stats.class(64).retained, 1);
+    assert_eq!(stats.class(64).live, 0);
+  }
+
+  #[test]
+  fn map_in_place_transforms_each_byte() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"frame");
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    let mut i = 0;
+    buf.map_in_place(|b| {
+      let m = mask[i % 4];
+      i += 1;
+      b ^ m
+    });
+    assert_ne!(&buf[..], b"frame");
+    // Masking again unmasks.
+    let mut i = 0;
+    buf.map_in_place(|b| {
+      let m = mask[i % 4];
+      i += 1;
+      b ^ m
+    });
+    assert_eq!(&buf[..], b"frame");
+
+    buf.map_in_place(|b| b);
+    assert_eq!(&buf[..], b"frame");
+    assert_eq!(buf.len(), 5);
+  }
+
+  #[test]
+  fn chunks_mut_visit_each_block() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(&[0; 10]);
+    for (i, block) in buf.chunks_mut(4).enumerate() {
+      block.fill(i as u8 + 1);
+    }
+    assert_eq!(&buf[..], &[1, 1, 1, 1, 2, 2, 2, 2, 3, 3]);
+
+    let mut blocks = buf.chunks_exact_mut(4);
+    for block in &mut blocks {
+      block[0] = 9;
+    }
+    assert_eq!(blocks.into_remainder(), &mut [3, 3]);
+    assert_eq!(&buf[..], &[9, 1, 1, 1, 9, 2, 2, 2, 3, 3]);
+  }
+
+  #[test]
+  fn rfind_and_rsplit_on_search_from_the_end() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(32);
+    buf.extend_from_slice(b"/usr/local/bin");
+    assert_eq!(buf.rfind(b'/'), Some(10));
+    assert_eq!(buf.rfind(b'n'), Some(13));
+    assert_eq!(buf.rfind(b'x'), None);
+    assert_eq!(
+      buf.rsplit_on(b'/').collect::<Vec<_>>(),
+      [&b"bin"[..], b"local", b"usr", b""]
+    );
+    assert_eq!(
+      buf.rsplit_on(b'x').collect::<Vec<_>>(),
+      [&b"/usr/local/bin"[..]]
+    );
+  }
+
+  #[test]
+  fn extend_from_within_copies_and_grows() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"abcdef");
+    // Fits within the capacity.
+    buf.extend_from_within(1..3);
+    assert_eq!(&buf[..], b"abcdefbc");
+    assert_eq!(buf.capacity(), 16);
+    // The whole contents double the length, exactly filling the capacity.
+    buf.extend_from_within(..);
+    assert_eq!(&buf[..], b"abcdefbcabcdefbc");
+    assert_eq!(buf.capacity(), 16);
+    // Doubling again requires growth.
+    buf.extend_from_within(0..buf.len());
+    assert_eq!(buf.len(), 32);
+    assert_eq!(&buf[16..], b"abcdefbcabcdefbc");
+    buf.extend_from_within(..1);
+    assert_eq!(buf.len(), 33);
+    assert!(buf.capacity() >= 33);
+    assert_eq!(buf[32], b'a');
+  }
+
+  #[test]
+  fn insert_slice_at_front_middle_and_end() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"body");
+    buf.insert_slice(0, b"hdr:");
+    assert_eq!(&buf[..], b"hdr:body");
+    assert_eq!(buf.capacity(), 8);
+    // Needs to grow.
+    buf.insert_slice(4, b" ");
+    assert_eq!(&buf[..], b"hdr: body");
+    buf.insert_slice(buf.len(), b"\r\n");
+    assert_eq!(&buf[..], b"hdr: body\r\n");
+    buf.insert_slice(3, b"");
+    assert_eq!(&buf[..], b"hdr: body\r\n");
+  }
+
+  #[test]
+  #[should_panic(expected = "insertion index 5 is out of bounds for length 4")]
+  fn insert_slice_past_end_panics() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"body");
+    buf.insert_slice(5, b"x");
+  }
+
+  #[cfg(feature = "checksum")]
+  #[test]
+  fn checksums_match_known_values() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    assert_eq!(buf.crc32(), 0);
+    assert_eq!(buf.adler32(), 1);
+    buf.extend_from_slice(b"123456789");
+    assert_eq!(buf.crc32(), 0xcbf43926);
+    buf.clear();
+    buf.extend_from_slice(b"Wikipedia");
+    assert_eq!(buf.adler32(), 0x11e60398);
+  }
+
+  #[cfg(feature = "read-buf")]
+  #[test]
+  fn with_unfilled_appends_what_read_buf_writes() {
+    use std::io::Read;
+
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"ab");
+    let mut reader = &b"cdefghijk"[..];
+    buf.with_unfilled(|cursor| reader.read_buf(cursor)).unwrap();
+    // Only the spare capacity is filled, and the rest stays in the reader.
+    assert_eq!(&buf[..], b"abcdefgh");
+    assert_eq!(reader, b"ijk");
+    // There's no spare capacity left, so nothing more is read.
+    buf.with_unfilled(|cursor| reader.read_buf(cursor)).unwrap();
+    assert_eq!(buf.len(), 8);
+  }
+
+  #[test]
+  fn spare_capacity_len_and_is_full() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    assert_eq!(buf.spare_capacity_len(), 8);
+    assert!(!buf.is_full());
+    buf.extend_from_slice(b"abc");
+    assert_eq!(buf.spare_capacity_len(), 5);
+    assert!(!buf.is_full());
+    buf.extend_from_slice(b"defgh");
+    assert_eq!(buf.spare_capacity_len(), 0);
+    assert!(buf.is_full());
+    // A zero-capacity Buf is both empty and full.
+    let empty = pool.allocate(0);
+    assert_eq!(empty.spare_capacity_len(), 0);
+    assert!(empty.is_full());
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn swap_with_returns_each_slab_to_its_pool() {
+    let small_pool = BufPool::new();
+    let large_pool = BufPool::new();
+    let mut a = small_pool.allocate(64);
+    a.extend_from_slice(b"small");
+    let mut b = large_pool.allocate(4096);
+    b.extend_from_slice(b"large");
+    a.swap_with(&mut b);
+    assert_eq!(&a[..], b"large");
+    assert_eq!(a.capacity(), 4096);
+    assert_eq!(&b[..], b"small");
+    drop(a);
+    drop(b);
+    assert_eq!(small_pool.stats().class(64).retained, 1);
+    assert_eq!(small_pool.stats().class(4096).retained, 0);
+    assert_eq!(large_pool.stats().class(4096).retained, 1);
+    assert_eq!(large_pool.stats().class(64).retained, 0);
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn memfd_backed_buf_can_be_spliced_to_a_pipe() {
+    use std::os::fd::AsRawFd;
+
+    let pool = BufPool::memfd_backed();
+    let mut buf = pool.allocate(4096);
+    buf.extend_from_slice(b"zero copy");
+    let fd = buf.as_fd().unwrap();
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    let mut offset: libc::loff_t = 0;
+    let n = unsafe {
+      libc::splice(
+        fd.as_raw_fd(),
+        &mut offset,
+        pipe[1],
+        std::ptr::null_mut(),
+        buf.len(),
+        0,
+      )
+    };
+    assert_eq!(n, 9);
+    let mut out = [0u8; 16];
+    let n = unsafe { libc::read(pipe[0], out.as_mut_ptr().cast(), out.len()) };
+    assert_eq!(&out[..n as usize], b"zero copy");
+    unsafe {
+      libc::close(pipe[0]);
+      libc::close(pipe[1]);
+    };
+
+    assert!(BufPool::new().allocate(64).as_fd().is_none());
+  }
+
+  #[cfg(feature = "base64")]
+  #[test]
+  fn base64_round_trips_binary_data() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(256);
+    buf.extend(0..=255);
+    let encoded = buf.to_base64();
+    assert_eq!(encoded.len(), 344);
+    assert_eq!(&encoded[..8], b"AAECAwQF");
+    let decoded = pool.from_base64(&encoded).unwrap();
+    assert_eq!(decoded, buf);
+
+    let mut short = pool.allocate(4);
+    short.extend_from_slice(b"ab");
+    assert_eq!(&short.to_base64()[..], b"YWI=");
+    assert!(pool.from_base64(b"YWI").is_err());
+    assert!(pool.from_base64(b"").unwrap().is_empty());
+  }
+
+  #[test]
+  fn inherent_len_isnt_ambiguous_with_trait_len() {
+    // A trait with its own `len`, as in code that's generic over frames.
+    trait Frame {
+      fn len(&self) -> usize;
+      fn is_empty(&self) -> bool;
+    }
+
+    impl Frame for Buf {
+      fn len(&self) -> usize {
+        // Includes a 4-byte length prefix.
+        Buf::len(self) + 4
+      }
+
+      fn is_empty(&self) -> bool {
+        false
+      }
+    }
+
+    fn frame_len<T: Frame>(frame: &T) -> (usize, bool) {
+      (frame.len(), frame.is_empty())
+    }
+
+    fn contents_len<T: AsRef<[u8]>>(data: &T) -> usize {
+      data.as_ref().len()
+    }
+
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    assert_eq!((buf.len(), buf.is_empty()), (0, true));
+    buf.extend_from_slice(b"abc");
+    // Method calls on a Buf resolve to the inherent methods, not the trait's or the slice's.
+    assert_eq!((buf.len(), buf.is_empty()), (3, false));
+    assert_eq!(frame_len(&buf), (7, false));
+    assert_eq!(contents_len(&buf), 3);
+  }
+
+  #[test]
+  fn try_set_len_and_set_len_saturating() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate_with_zeros(8);
+    buf.clear();
+    assert_eq!(unsafe { buf.try_set_len(8) }, Ok(()));
+    assert_eq!(buf.len(), 8);
+    assert_eq!(
+      unsafe { buf.try_set_len(9) },
+      Err(LenExceedsCapacity { len: 9, cap: 8 })
+    );
+    // The length is unchanged on error.
+    assert_eq!(buf.len(), 8);
+
+    unsafe { buf.set_len_saturating(3) };
+    assert_eq!(buf.len(), 3);
+    unsafe { buf.set_len_saturating(usize::MAX) };
+    assert_eq!(buf.len(), 8);
+  }
+
+  #[test]
+  fn binary_search_and_partition_point_on_sorted_contents() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(&[1, 3, 5, 7, 9]);
+    assert_eq!(buf.binary_search(&5), Ok(2));
+    assert_eq!(buf.binary_search(&1), Ok(0));
+    assert_eq!(buf.binary_search(&4), Err(2));
+    assert_eq!(buf.binary_search(&10), Err(5));
+    assert_eq!(buf.partition_point(|&b| b < 6), 3);
+    assert_eq!(buf.partition_point(|&b| b < 100), 5);
+    assert_eq!(buf.partition_point(|_| false), 0);
+    // Only the contents are searched, not the spare capacity.
+    assert_eq!(pool.allocate(8).binary_search(&0), Err(0));
+  }
+
+  #[cfg(feature = "fixed")]
+  #[test]
+  fn compares_equal_to_fixed_buf_with_the_same_contents() {
+    let pool = BufPool::new();
+    let fixed_pool = bufpool_fixed::FixedBufPool::new();
+    let fixed = fixed_pool.allocate_from_data(*b"abcd");
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"abcd");
+    assert!(buf == fixed);
+    assert!(fixed == buf);
+
+    // A different byte, or a length that doesn't match the FixedBuf's capacity, is unequal.
+    buf[3] = b'x';
+    assert!(buf != fixed);
+    assert!(fixed != buf);
+    buf.truncate(3);
+    assert!(buf != fixed);
+    assert!(fixed != buf);
+  }
+
+  #[test]
+  fn iter_indexed_pairs_positions_with_bytes() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"abc");
+    assert_eq!(
+      buf.iter_indexed().collect::<Vec<_>>(),
+      [(0, b'a'), (1, b'b'), (2, b'c')]
+    );
+    for (i, b) in buf.iter_mut_indexed() {
+      *b += i as u8;
+    }
+    assert_eq!(&buf[..], b"ace");
+    assert_eq!(pool.allocate(8).iter_indexed().count(), 0);
+  }
+
+  #[test]
+  fn copy_to_slice_exact_fit_and_truncating() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"abcd");
+    let mut dst = [0u8; 4];
+    buf.copy_to_slice(&mut dst);
+    assert_eq!(&dst, b"abcd");
+    // A longer destination keeps its trailing bytes.
+    let mut dst = [b'.'; 6];
+    buf.copy_to_slice(&mut dst);
+    assert_eq!(&dst, b"abcd..");
+
+    let mut dst = [0u8; 2];
+    assert_eq!(buf.copy_to_slice_truncating(&mut dst), 2);
+    assert_eq!(&dst, b"ab");
+    let mut dst = [b'.'; 6];
+    assert_eq!(buf.copy_to_slice_truncating(&mut dst), 4);
+    assert_eq!(&dst, b"abcd..");
+  }
+
+  #[test]
+  #[should_panic(expected = "destination of 3 bytes is shorter than length 4")]
+  fn copy_to_slice_too_small_panics() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"abcd");
+    buf.copy_to_slice(&mut [0u8; 3]);
+  }
+
+  #[test]
+  fn set_len_filled_zeroes_growth_and_truncates() {
+    let pool = BufPool::new();
+    // Dirty a slab so that the reused one has stale bytes beyond the length.
+    drop(pool.allocate_with_fill(0xff, 8));
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"ab");
+    buf.set_len_filled(6);
+    assert_eq!(&buf[..], b"ab\0\0\0\0");
+    // Past the capacity.
+    buf.set_len_filled(12);
+    assert_eq!(&buf[..], b"ab\0\0\0\0\0\0\0\0\0\0");
+    buf[1..4].copy_from_slice(b"xyz");
+    buf.set_len_filled(1);
+    assert_eq!(&buf[..], b"a");
+    // Growing again zeroes the bytes that were truncated.
+    buf.set_len_filled(3);
+    assert_eq!(&buf[..], b"a\0\0");
+  }
+
+  #[test]
+  fn as_chunks_splits_off_the_remainder() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(64);
+    buf.extend(0..32);
+    let (blocks, rem) = buf.as_chunks::<16>();
+    assert_eq!(blocks.len(), 2);
+    assert!(rem.is_empty());
+    assert_eq!(blocks[1][0], 16);
+
+    buf.extend_from_slice(&[1, 2, 3]);
+    let (blocks, rem) = buf.as_chunks::<16>();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(rem, &[1, 2, 3]);
+
+    let (blocks, rem) = buf.as_chunks_mut::<16>();
+    for block in blocks {
+      block.fill(0);
+    }
+    rem.fill(9);
+    assert!(buf[..32].iter().all(|&b| b == 0));
+    assert_eq!(&buf[32..], &[9, 9, 9]);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn prepend_swaps_into_an_empty_buf() {
+    let header_pool = BufPool::new();
+    let body_pool = BufPool::new();
+    let mut header = header_pool.allocate(64);
+    let mut body = body_pool.allocate(4096);
+    body.extend_from_slice(b"body");
+    let body_ptr = body.as_ptr();
+    header.prepend(&mut body);
+    // The slabs were swapped rather than copied.
+    assert_eq!(&header[..], b"body");
+    assert_eq!(header.as_ptr(), body_ptr);
+    assert!(body.is_empty());
+    assert_eq!(body.capacity(), 64);
+    drop((header, body));
+    assert_eq!(header_pool.retained_count(64), 1);
+    assert_eq!(body_pool.retained_count(4096), 1);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn prepend_shifts_into_a_non_empty_buf() {
+    let pool = BufPool::new();
+    let other_pool = BufPool::new();
+    let mut frame = pool.allocate(8);
+    frame.extend_from_slice(b"payload");
+    let mut prefix = other_pool.allocate(8);
+    prefix.extend_from_slice(b"len:");
+    frame.prepend(&mut prefix);
+    // Grown past the capacity from its own pool, and `prefix` keeps its slab.
+    assert_eq!(&frame[..], b"len:payload");
+    assert_eq!(frame.capacity(), 16);
+    assert!(prefix.is_empty());
+    assert_eq!(prefix.capacity(), 8);
+    assert_eq!(pool.retained_count(8), 1);
+    drop(frame);
+    drop(prefix);
+    assert_eq!(pool.retained_count(16), 1);
+    assert_eq!(other_pool.retained_count(8), 1);
+  }
+
+  #[test]
+  fn alignment_matches_the_pool() {
+    for align in [8, 64, 512, 4096] {
+      let pool = BufPool::with_alignment(align);
+      for cap in [1, 100, 8192] {
+        let buf = pool.allocate(cap);
+        assert_eq!(buf.alignment(), align);
+        assert!(buf.is_aligned_to(align));
+        assert!(buf.is_aligned_to(1));
+      }
+    }
+    // A buffer aligned to 4096 is also aligned to any smaller power of two.
+    let buf = BufPool::with_alignment(4096).allocate(1);
+    assert!(buf.is_aligned_to(512));
+  }
+
+  #[test]
+  #[should_panic]
+  fn is_aligned_to_requires_a_power_of_two() {
+    BufPool::new().allocate(1).is_aligned_to(3);
+  }
+
+  #[test]
+  fn extend_from_slices_appends_in_order_with_one_reservation() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(4);
+    buf.extend_from_slice(b"GET");
+    buf.extend_from_slices(&[b" ", b"/index.html", b"", b" HTTP/1.1\r\n"]);
+    assert_eq!(&buf[..], b"GET /index.html HTTP/1.1\r\n");
+    // Grown once, straight to the class for the total, rather than through each intermediate class.
+    assert_eq!(buf.capacity(), 32);
+    let hist = pool.size_histogram();
+    assert_eq!(hist[2], 1);
+    assert_eq!(hist[5], 1);
+    assert_eq!(hist.iter().sum::<u64>(), 2);
+
+    buf.extend_from_slices(&[]);
+    assert_eq!(buf.len(), 26);
+  }
+
+  #[cfg(feature = "origin-check")]
+  #[test]
+  #[should_panic(expected = "is being returned to a pool that didn't allocate it")]
+  fn origin_check_catches_return_to_another_pool() {
+    let origin = BufPool::new();
+    let other = BufPool::new();
+    let (data, len, cap) = origin.allocate(64).into_raw_parts();
+    drop(unsafe { Buf::from_raw_parts(&other, data, len, cap) });
+  }
+
+  #[cfg(feature = "origin-check")]
+  #[test]
+  fn origin_check_allows_return_to_the_same_pool() {
+    let pool = BufPool::new();
+    let (data, len, cap) = pool.allocate(64).into_raw_parts();
+    drop(unsafe { Buf::from_raw_parts(&pool, data, len, cap) });
+    assert_eq!(pool.retained_count(64), 1);
+  }
+
+  #[test]
+  fn slice_to_owned_copies_the_range() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"key=value");
+    let key = buf.slice_to_owned(..3);
+    assert_eq!(&key[..], b"key");
+    assert_eq!(key.capacity(), 4);
+    assert_eq!(&buf.slice_to_owned(4..)[..], b"value");
+    assert_eq!(&buf.slice_to_owned(..)[..], b"key=value");
+    assert!(buf.slice_to_owned(3..3).is_empty());
+    // The source is unchanged.
+    assert_eq!(&buf[..], b"key=value");
+  }
+
+  #[test]
+  #[should_panic(expected = "range end 10 is out of bounds for length 9")]
+  fn slice_to_owned_out_of_bounds_panics() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"key=value");
+    buf.slice_to_owned(4..10);
+  }
+
+  #[test]
+  fn fmt_write_appends_formatted_text() {
+    use std::fmt::Write;
+
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(4);
+    buf.extend_from_slice(b">");
+    let key = "id";
+    write!(buf, "{key}={:#x}", 255).unwrap();
+    writeln!(buf, " ok").unwrap();
+    writeln!(buf).unwrap();
+    assert_eq!(&buf[..], b">id=0xff ok\n\n");
+    let c = 'é';
+    write!(buf, "{c}").unwrap();
+    assert_eq!(&buf[13..], "é".as_bytes());
+  }
+
+  #[test]
+  fn reverse_and_rotate_match_vec() {
+    let pool = BufPool::new();
+    let data = (0..13).collect::<Vec<u8>>();
+    for mid in [0, 1, 5, 13] {
+      let mut buf = pool.allocate_from_data(&data);
+      let mut vec = data.clone();
+      buf.rotate_left(mid);
+      vec.rotate_left(mid);
+      assert_eq!(&buf[..], &vec[..]);
+      buf.rotate_right(mid);
+      vec.rotate_right(mid);
+      assert_eq!(&buf[..], &vec[..]);
+      buf.rotate_right(mid);
+      vec.rotate_right(mid);
+      assert_eq!(&buf[..], &vec[..]);
+      buf.reverse();
+      vec.reverse();
+      assert_eq!(&buf[..], &vec[..]);
+    }
+    // Only the contents are reordered, not the spare capacity.
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"abc");
+    buf.reverse();
+    assert_eq!(&buf[..], b"cba");
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn compact_moves_to_smaller_slab_below_quarter_capacity() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(1024);
+    buf.extend_from_slice(&[7u8; 100]);
+    buf.compact();
+    assert_eq!(buf.capacity(), 128);
+    assert_eq!(buf.as_slice(), &[7u8; 100]);
+    assert_eq!(pool.retained_count(1024), 1);
+  }
+
+  #[test]
+  fn compact_is_noop_at_or_above_quarter_capacity() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(1024);
+    buf.extend_from_slice(&[7u8; 256]);
+    let ptr = buf.as_slice().as_ptr();
+    buf.compact();
+    assert_eq!(buf.capacity(), 1024);
+    assert_eq!(buf.as_slice().as_ptr(), ptr);
+    assert_eq!(pool.retained_count(1024), 0);
+  }
+
+  #[test]
+  fn tail_writer_appends_through_dyn_write() {
+    use std::io::Write;
+
+    fn emit(out: &mut dyn Write) {
+      out.write_all(b" world").unwrap();
+      write!(out, "{}", 42).unwrap();
+    }
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"hello");
+    {
+      let mut w = buf.tail_writer();
+      emit(&mut w);
+      assert_eq!(w.written(), 8);
+    }
+    assert_eq!(buf.as_slice(), b"hello world42");
+    assert_eq!(buf.capacity(), 16);
+  }
+
+  #[test]
+  fn eq_checks_len_before_pointer_fast_path() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"abcdef");
+    let (data, len, cap) = buf.into_raw_parts();
+    // Two Bufs sharing a slab, standing in for misuse of `from_raw_parts`.
+    let a = unsafe { Buf::from_raw_parts(&pool, data, len, cap) };
+    let b = unsafe { Buf::from_raw_parts(&pool, data, 3, cap) };
+    assert!(a != b);
+    let c = unsafe { Buf::from_raw_parts(&pool, data, len, cap) };
+    assert!(a == c);
+    // Only one of them may return the slab.
+    let _ = b.into_raw_parts();
+    let _ = c.into_raw_parts();
+  }
+
+  #[test]
+  fn eq_compares_contents_of_distinct_slabs() {
+    let pool = BufPool::new();
+    let mut a = pool.allocate(16);
+    a.extend_from_slice(b"abc");
+    let mut b = pool.allocate(16);
+    b.extend_from_slice(b"abc");
+    assert_eq!(a, b);
+    b.as_mut_slice()[2] = b'x';
+    assert_ne!(a, b);
+  }
+
+  #[test]
+  fn eq_bytes_compares_contents() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"abc");
+    assert!(buf.eq_bytes(b"abc"));
+    assert!(!buf.eq_bytes(b"abd"));
+    assert!(!buf.eq_bytes(b"ab"));
+    assert!(!buf.eq_bytes(b"abcd"));
+    assert!(pool.allocate(0).eq_bytes(&[]));
+  }
+
+  #[test]
+  fn scatter_into_exact_fit_skips_full_dests() {
+    let pool = BufPool::new();
+    let mut src = pool.allocate(8);
+    src.extend_from_slice(b"abcdefgh");
+    let mut full = pool.allocate(2);
+    full.extend_from_slice(b"xy");
+    let mut dests = [full, pool.allocate(0), pool.allocate(4), pool.allocate(4)];
+    assert_eq!(src.scatter_into(&mut dests), (8, 4));
+    assert_eq!(dests[0].as_slice(), b"xy");
+    assert_eq!(dests[2].as_slice(), b"abcd");
+    assert_eq!(dests[3].as_slice(), b"efgh");
+  }
+
+  #[test]
+  fn scatter_into_stops_when_dests_overflow() {
+    let pool = BufPool::new();
+    let mut src = pool.allocate(16);
+    src.extend_from_slice(b"abcdefghij");
+    let mut dests = [pool.allocate(4), pool.allocate(4)];
+    assert_eq!(src.scatter_into(&mut dests), (8, 2));
+    assert_eq!(dests[0].as_slice(), b"abcd");
+    assert_eq!(dests[1].as_slice(), b"efgh");
+    assert_eq!(dests[1].capacity(), 4);
+  }
+
+  #[test]
+  fn scatter_into_stops_at_partially_filled_dest() {
+    let pool = BufPool::new();
+    let mut src = pool.allocate(8);
+    src.extend_from_slice(b"abcdef");
+    let mut dests = [pool.allocate(4), pool.allocate(4), pool.allocate(4)];
+    assert_eq!(src.scatter_into(&mut dests), (6, 1));
+    assert_eq!(dests[1].as_slice(), b"ef");
+    assert!(dests[2].is_empty());
+  }
+
+  #[test]
+  fn collapse_runs_reduces_runs_to_one_byte() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(32);
+    buf.extend_from_slice(b"  a   b c  ");
+    buf.collapse_runs(b' ');
+    assert_eq!(buf.as_slice(), b" a b c ");
+    buf.collapse_runs(b'x');
+    assert_eq!(buf.as_slice(), b" a b c ");
+    buf.clear();
+    buf.extend_from_slice(b"aaaa");
+    buf.collapse_runs(b'a');
+    assert_eq!(buf.as_slice(), b"a");
+    buf.clear();
+    buf.collapse_runs(b'a');
+    assert!(buf.is_empty());
+  }
+
+  #[test]
+  fn as_str_and_to_string_lossy() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    assert_eq!(buf.as_str().unwrap(), "");
+    assert_eq!(buf.to_string_lossy(), "");
+    buf.extend_from_slice("héllo".as_bytes());
+    assert_eq!(buf.as_str().unwrap(), "héllo");
+    assert!(matches!(
+      buf.to_string_lossy(),
+      std::borrow::Cow::Borrowed("héllo")
+    ));
+    buf.extend_from_slice(&[0xff, b'!']);
+    let err = buf.as_str().unwrap_err();
+    assert_eq!(err.valid_up_to(), 6);
+    assert_eq!(buf.to_string_lossy(), "héllo\u{fffd}!");
+  }
+
+  #[test]
+  fn copy_from_buf_reuses_slab_when_large_enough() {
+    let pool = BufPool::new();
+    let mut src = pool.allocate(64);
+    src.extend_from_slice(&[3u8; 64]);
+    let mut dst = pool.allocate(64);
+    dst.extend_from_slice(b"old contents");
+    let ptr = dst.as_slice().as_ptr();
+    let live = pool.stats().class(64).live;
+    dst.copy_from_buf(&src);
+    assert_eq!(dst, src);
+    assert_eq!(dst.as_slice().as_ptr(), ptr);
+    assert_eq!(dst.capacity(), 64);
+    assert_eq!(pool.stats().class(64).live, live);
+    assert_eq!(pool.stats().class(128).live, 0);
+  }
+
+  #[test]
+  fn copy_from_buf_grows_when_too_small() {
+    let pool = BufPool::new();
+    let mut src = pool.allocate(64);
+    src.extend_from_slice(&[3u8; 40]);
+    let mut dst = pool.allocate(16);
+    dst.copy_from_buf(&src);
+    assert_eq!(dst, src);
+    assert_eq!(dst.capacity(), 64);
+  }
+
+  #[test]
+  fn cursor_patches_length_prefix() {
+    use std::io::Seek;
+    use std::io::SeekFrom;
+    use std::io::Write;
+
+    let pool = BufPool::new();
+    let mut cur = pool.allocate(4).into_cursor();
+    cur.write_all(&[0; 4]).unwrap();
+    cur.write_all(b"payload").unwrap();
+    let body_len = (cur.position() - 4) as u32;
+    cur.seek(SeekFrom::Start(0)).unwrap();
+    cur.write_all(&body_len.to_be_bytes()).unwrap();
+    assert_eq!(cur.position(), 4);
+    cur.seek(SeekFrom::End(0)).unwrap();
+    cur.write_all(b"!").unwrap();
+    let buf = cur.into_buf();
+    assert_eq!(buf.as_slice(), b"\0\0\0\x07payload!");
+  }
+
+  #[test]
+  fn cursor_seek_past_end_zero_fills() {
+    use std::io::Seek;
+    use std::io::SeekFrom;
+    use std::io::Write;
+
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(b"ab");
+    let mut cur = buf.into_cursor();
+    assert_eq!(cur.seek(SeekFrom::Current(5)).unwrap(), 5);
+    cur.write_all(b"z").unwrap();
+    assert_eq!(cur.seek(SeekFrom::End(2)).unwrap(), 8);
+    assert!(cur.seek(SeekFrom::Current(-9)).is_err());
+    assert_eq!(cur.position(), 8);
+    assert_eq!(cur.into_buf().as_slice(), b"ab\0\0\0z\0\0");
+  }
+
+  #[test]
+  fn chunk_by_yields_runs() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    assert_eq!(buf.chunk_by(|a, b| a == b).count(), 0);
+    buf.extend_from_slice(b"aaabccd");
+    let runs: Vec<&[u8]> = buf.chunk_by(|a, b| a == b).collect();
+    assert_eq!(runs, [&b"aaa"[..], b"b", b"cc", b"d"]);
+    let ascending: Vec<&[u8]> = buf.chunk_by(|a, b| a < b).collect();
+    assert_eq!(ascending, [&b"a"[..], b"a", b"abc", b"cd"]);
+  }
+
+  #[test]
+  fn extend_zeroed_appends_zeros_over_stale_bytes() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(8);
+    buf.extend_from_slice(&[0xff; 8]);
+    buf.truncate(2);
+    buf.extend_zeroed(4);
+    assert_eq!(buf.as_slice(), &[0xff, 0xff, 0, 0, 0, 0]);
+    buf.extend_zeroed(0);
+    assert_eq!(buf.len(), 6);
+    buf.extend_zeroed(10);
+    assert_eq!(buf.len(), 16);
+    assert_eq!(buf.capacity(), 16);
+    assert!(buf[2..].iter().all(|&b| b == 0));
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn freeze_range_returns_slab_once_after_last_view() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"headbodytail");
+    let body = buf.freeze_range(4..8);
+    assert_eq!(body.as_slice(), b"body");
+    let clone = body.clone();
+    assert_eq!(&clone[..], b"body");
+    drop(body);
+    assert_eq!(pool.retained_count(16), 0);
+    assert_eq!(pool.stats().class(16).live, 1);
+    drop(clone);
+    assert_eq!(pool.retained_count(16), 1);
+    assert_eq!(pool.stats().class(16).live, 0);
+  }
+
+  #[test]
+  #[should_panic(expected = "range end 4 is out of bounds for length 3")]
+  fn freeze_range_out_of_bounds_panics() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"abc");
+    let _ = buf.freeze_range(2..4);
+  }
+
+  #[cfg(feature = "fixed")]
+  #[test]
+  fn into_fixed_round_trip_zero_pads() {
+    let pool = BufPool::new();
+    let fixed_pool = bufpool_fixed::FixedBufPool::new();
+    // Leave a dirty slab in the fixed pool for `into_fixed` to reuse.
+    drop(fixed_pool.allocate_from_data([0xffu8; 8]));
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"hello");
+    let fixed = buf.into_fixed(&fixed_pool);
+    assert_eq!(fixed.capacity(), 8);
+    assert_eq!(fixed.as_slice(), b"hello\0\0\0");
+    assert_eq!(pool.retained_count(16), 1);
+
+    let mut back = pool.allocate(fixed.capacity());
+    back.extend_from_slice(fixed.as_slice());
+    assert!(back == fixed);
+  }
+
+  #[test]
+  fn retain_mut_filters_and_modifies_kept_bytes() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"He-LLo, W0rld!");
+    buf.retain_mut(|b| {
+      b.make_ascii_lowercase();
+      b.is_ascii_alphabetic()
+    });
+    assert_eq!(buf.as_slice(), b"hellowrld");
+    buf.retain_mut(|_| false);
+    assert!(buf.is_empty());
+    assert_eq!(buf.capacity(), 16);
+  }
+
+  #[test]
+  fn shift_left_discards_prefix() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"GET / HTTP/1.1");
+    buf.shift_left(4);
+    assert_eq!(buf.as_slice(), b"/ HTTP/1.1");
+    buf.shift_left(0);
+    assert_eq!(buf.as_slice(), b"/ HTTP/1.1");
+    buf.shift_left(10);
+    assert!(buf.is_empty());
+    assert_eq!(buf.capacity(), 16);
+  }
+
+  #[test]
+  #[should_panic(expected = "shift of 4 is out of bounds for length 3")]
+  fn shift_left_past_end_panics() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"abc");
+    buf.shift_left(4);
+  }
+
+  #[test]
+  fn windows_overlap_and_are_empty_when_size_exceeds_len() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"abcd");
+    let windows: Vec<&[u8]> = buf.windows(2).collect();
+    assert_eq!(windows, [&b"ab"[..], b"bc", b"cd"]);
+    assert_eq!(buf.windows(4).count(), 1);
+    // Only the contents are windowed, not the spare capacity.
+    assert_eq!(buf.windows(5).count(), 0);
+  }
+
+  #[test]
+  #[should_panic(expected = "window size must be non-zero")]
+  fn windows_of_zero_size_panics() {
+    let pool = BufPool::new();
+    let _ = pool.allocate(16).windows(0);
+  }
+
+  #[test]
+  fn guard_commits_or_rolls_back_appended_bytes() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(16);
+    buf.extend_from_slice(b"ok;");
+
+    let mut guard = buf.guard();
+    guard.extend_from_slice(b"good;");
+    guard.commit();
+    assert_eq!(buf.as_slice(), b"ok;good;");
+
+    {
+      let mut guard = buf.guard();
+      guard.extend_from_slice(b"partial");
+      assert_eq!(guard.len(), 15);
+    }
+    assert_eq!(buf.as_slice(), b"ok;good;");
+
+    // Growing within a rolled back guard keeps the larger slab, but not the bytes.
+    {
+      let mut guard = buf.guard();
+      guard.extend_zeroed(100);
+    }
+    assert_eq!(buf.as_slice(), b"ok;good;");
+    assert_eq!(buf.capacity(), 128);
+  }
+
+  #[test]
+  fn read_until_from_reads_delimited_lines_then_eof() {
+    // A small BufReader makes the lines span several `fill_buf` calls.
+    let mut reader = std::io::BufReader::with_capacity(4, &b"first line\nsecond"[..]);
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(4);
+    buf.extend_from_slice(b"> ");
+    assert_eq!(buf.read_until_from(&mut reader, b'\n').unwrap(), 11);
+    assert_eq!(buf.as_slice(), b"> first line\n");
+
+    buf.clear();
+    assert_eq!(buf.read_until_from(&mut reader, b'\n').unwrap(), 6);
+    assert_eq!(buf.as_slice(), b"second");
+    assert_eq!(buf.read_until_from(&mut reader, b'\n').unwrap(), 0);
+    assert_eq!(buf.as_slice(), b"second");
+  }
+}
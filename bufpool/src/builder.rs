@@ -0,0 +1,268 @@
+use crate::stats::PoolStats;
+use crate::AlignmentError;
+use crate::Backing;
+use crate::BufPool;
+use crate::BufPoolInner;
+use crate::PressureCallback;
+use std::mem::size_of;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+/// Configures a `BufPool`. The defaults match `BufPool::new()`.
+#[derive(Clone)]
+pub struct BufPoolBuilder {
+  align: usize,
+  backing: Backing,
+  transparent_hugepages: bool,
+  zero_on_free: bool,
+  prefault: bool,
+  max_retained_per_class: usize,
+  min_pool_class: usize,
+  thread_local_cache: bool,
+  max_live: usize,
+  max_alloc: usize,
+  pressure: Option<PressureCallback>,
+}
+
+impl BufPoolBuilder {
+  pub fn new() -> Self {
+    Self {
+      align: size_of::<usize>(),
+      backing: Backing::Heap,
+      transparent_hugepages: false,
+      zero_on_free: false,
+      prefault: false,
+      max_retained_per_class: usize::MAX,
+      min_pool_class: 0,
+      thread_local_cache: false,
+      max_live: usize::MAX,
+      max_alloc: usize::MAX,
+      pressure: None,
+    }
+  }
+
+  /// Must be a nonzero power of two. For mmap-backed pools, this cannot exceed the page size.
+  pub fn alignment(mut self, align: usize) -> Self {
+    self.align = align;
+    self
+  }
+
+  /// See `BufPool::mmap_backed`. This raises the alignment to the page size, as that's what `mmap` provides.
+  #[cfg(unix)]
+  pub fn mmap_backed(mut self) -> Self {
+    self.backing = Backing::Mmap;
+    self.align = self.align.max(crate::page_size());
+    self
+  }
+
+  /// See `BufPool::memfd_backed`. Like `mmap_backed`, this raises the alignment to the page size.
+  #[cfg(target_os = "linux")]
+  pub fn memfd_backed(mut self) -> Self {
+    self.backing = Backing::Memfd;
+    self.align = self.align.max(crate::page_size());
+    self
+  }
+
+  /// See `BufPool::jemalloc_backed`.
+  #[cfg(feature = "jemalloc")]
+  pub fn jemalloc_backed(mut self) -> Self {
+    self.backing = Backing::Jemalloc;
+    self
+  }
+
+  /// See `BufPool::mimalloc_backed`.
+  #[cfg(feature = "mimalloc")]
+  pub fn mimalloc_backed(mut self) -> Self {
+    self.backing = Backing::Mimalloc;
+    self
+  }
+
+  /// See `BufPool::with_max_alloc`. Unlimited by default.
+  pub fn max_alloc(mut self, max: usize) -> Self {
+    self.max_alloc = max;
+    self
+  }
+
+  /// See `BufPool::with_max_live`. Unlimited by default.
+  pub fn max_live(mut self, max: usize) -> Self {
+    self.max_live = max;
+    self
+  }
+
+  /// See `BufPool::with_limits`. Unlimited by default.
+  pub fn max_retained_per_class(mut self, max: usize) -> Self {
+    self.max_retained_per_class = max;
+    self
+  }
+
+  /// Slabs with a capacity below `cap` are freed instead of being retained when returned to the pool, as pooling tiny slabs costs more than it saves. Everything is retained by default.
+  pub fn min_pool_class(mut self, cap: usize) -> Self {
+    self.min_pool_class = cap;
+    self
+  }
+
+  /// See `BufPool::with_prefault`.
+  pub fn prefault(mut self, enabled: bool) -> Self {
+    self.prefault = enabled;
+    self
+  }
+
+  /// See `BufPool::with_pressure_callback`.
+  pub fn pressure_callback(
+    mut self,
+    high_water: usize,
+    f: impl Fn(PoolStats) + Send + Sync + 'static,
+  ) -> Self {
+    self.pressure = Some(PressureCallback {
+      high_water,
+      callback: Arc::new(f),
+    });
+    self
+  }
+
+  /// Cache a few returned slabs of each size in the dropping thread, so that drop-heavy workloads take the pool's locks once per batch instead of once per Buf. Allocations check the current thread's cache first.
+  ///
+  /// Cached slabs aren't counted by `stats`, and only the calling thread's cache is returned to the pool by `trim`; other threads return theirs when their cache fills up or they exit. Caches don't keep the pool alive; if it's dropped, each thread frees the slabs it cached for the pool the next time it uses any pool's cache, or when it exits.
+  pub fn thread_local_cache(mut self, enabled: bool) -> Self {
+    self.thread_local_cache = enabled;
+    self
+  }
+
+  /// Advise the kernel to back slabs of 2 MiB or more with transparent huge pages, which reduces TLB pressure when copying through large buffers. This only applies to mmap-backed pools, and is best effort: it's silently ignored where THP is unavailable or disabled.
+  #[cfg(unix)]
+  pub fn transparent_hugepages(mut self, enabled: bool) -> Self {
+    self.transparent_hugepages = enabled;
+    self
+  }
+
+  /// Zero slabs when they're returned to the pool. This moves the cost of zeroing from `allocate_with_zeros` to `Drop`, and ensures stale data doesn't linger in retained memory.
+  pub fn zero_on_free(mut self, enabled: bool) -> Self {
+    self.zero_on_free = enabled;
+    self
+  }
+
+  /// Panics if the configuration is invalid; see `try_build`.
+  pub fn build(self) -> BufPool {
+    match self.try_build() {
+      Ok(pool) => pool,
+      Err(err) => panic!("{err}"),
+    }
+  }
+
+  /// Returns an error instead of panicking if the alignment is invalid, so that configuration errors can be surfaced gracefully.
+  pub fn try_build(self) -> Result<BufPool, AlignmentError> {
+    if self.align == 0 {
+      return Err(AlignmentError::Zero);
+    };
+    if !self.align.is_power_of_two() {
+      return Err(AlignmentError::NotPowerOfTwo { align: self.align });
+    };
+    #[cfg(unix)]
+    if self.backing.is_mapped() && self.align > crate::page_size() {
+      return Err(AlignmentError::ExceedsPageSize {
+        align: self.align,
+        page_size: crate::page_size(),
+      });
+    };
+    Ok(BufPool {
+      inner: Arc::new(BufPoolInner {
+        align: self.align,
+        backing: self.backing,
+        transparent_hugepages: self.transparent_hugepages,
+        zero_on_free: self.zero_on_free,
+        prefault: self.prefault,
+        max_retained_per_class: self.max_retained_per_class,
+        min_pool_class: self.min_pool_class,
+        thread_local_cache: self.thread_local_cache,
+        max_live: self.max_live,
+        max_alloc: self.max_alloc,
+        live: AtomicUsize::new(0),
+        retained_bytes: AtomicUsize::new(0),
+        pressure: self.pressure,
+        pressure_armed: AtomicBool::new(true),
+        #[cfg(feature = "origin-check")]
+        id: crate::next_pool_id(),
+        sizes: (0..usize::BITS as usize)
+          .map(|_| Default::default())
+          .collect(),
+        interned: Default::default(),
+        #[cfg(target_os = "linux")]
+        memfds: Default::default(),
+      }),
+    })
+  }
+}
+
+impl Default for BufPoolBuilder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::AllocError;
+  use crate::BufPool;
+
+  #[cfg(unix)]
+  #[test]
+  fn transparent_hugepages_slabs_are_usable() {
+    let pool = BufPool::builder()
+      .mmap_backed()
+      .transparent_hugepages(true)
+      .build();
+    // Both below and at the huge page size, so that slabs with and without the advice are exercised.
+    for cap in [4096, 4 * 1024 * 1024] {
+      let mut buf = pool.allocate(cap);
+      buf.extend((0..cap).map(|i| i as u8));
+      assert_eq!(buf.len(), cap);
+      assert!(buf.iter().enumerate().all(|(i, &b)| b == i as u8));
+    }
+  }
+
+  #[test]
+  fn build_applies_each_option() {
+    let pool = BufPool::builder()
+      .alignment(4096)
+      .zero_on_free(true)
+      .max_retained_per_class(1)
+      .min_pool_class(64)
+      .max_live(3)
+      .max_alloc(8192)
+      .build();
+
+    let mut a = pool.allocate(64);
+    a.extend_from_slice(&[1; 64]);
+    let b = pool.allocate(64);
+    let c = pool.allocate(32);
+    assert!(a.is_aligned_to(4096));
+    assert_eq!(
+      pool.try_allocate(64).unwrap_err(),
+      AllocError::LimitReached { max: 3 }
+    );
+    drop((a, b, c));
+
+    // Nothing is retained with `no-pool`, so there's no retention or zeroing on free to check.
+    #[cfg(not(feature = "no-pool"))]
+    {
+      let stats = pool.stats();
+      // Only one of the two 64-byte slabs is retained, and the 32-byte one is below the minimum class.
+      assert_eq!(stats.class(64).retained, 1);
+      assert_eq!(stats.class(32).retained, 0);
+
+      // The retained slab was zeroed when it was returned.
+      let mut reused = pool.allocate(64);
+      unsafe { reused.set_len(64) };
+      assert!(reused.iter().all(|&b| b == 0));
+    }
+
+    assert_eq!(
+      pool.try_allocate(8193).unwrap_err(),
+      AllocError::TooLarge {
+        requested: 8193,
+        max: 8192
+      }
+    );
+  }
+}
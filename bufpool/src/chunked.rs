@@ -0,0 +1,84 @@
+use crate::buf::Buf;
+use crate::BufPool;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::mem;
+
+/// Accumulates written bytes into pooled Bufs of exactly `chunk` bytes, for streaming encoders that emit fixed-size frames. Completed chunks are queued until taken with `next_chunk`; `flush` completes the current partial chunk.
+pub struct ChunkedWriter {
+  pool: BufPool,
+  chunk: usize,
+  current: Buf,
+  completed: VecDeque<Buf>,
+}
+
+impl ChunkedWriter {
+  pub(crate) fn new(pool: &BufPool, chunk: usize) -> Self {
+    assert!(chunk > 0);
+    Self {
+      pool: pool.clone(),
+      chunk,
+      current: pool.allocate(chunk),
+      completed: VecDeque::new(),
+    }
+  }
+
+  /// Takes the oldest completed chunk.
+  pub fn next_chunk(&mut self) -> Option<Buf> {
+    self.completed.pop_front()
+  }
+
+  fn complete_current(&mut self) {
+    let next = self.pool.allocate(self.chunk);
+    self
+      .completed
+      .push_back(mem::replace(&mut self.current, next));
+  }
+}
+
+impl Write for ChunkedWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let mut rem = buf;
+    while !rem.is_empty() {
+      let n = rem.len().min(self.chunk - self.current.len());
+      self.current.extend_from_slice(&rem[..n]);
+      rem = &rem[n..];
+      if self.current.len() == self.chunk {
+        self.complete_current();
+      };
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    if !self.current.is_empty() {
+      self.complete_current();
+    };
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::BufPool;
+  use std::io::Write;
+
+  #[test]
+  fn splits_writes_into_chunks_with_a_partial_tail() {
+    let pool = BufPool::new();
+    let mut w = pool.chunked_writer(4);
+    w.write_all(b"abcdef").unwrap();
+    w.write_all(b"ghijk").unwrap();
+    assert_eq!(&w.next_chunk().unwrap()[..], b"abcd");
+    assert_eq!(&w.next_chunk().unwrap()[..], b"efgh");
+    // The tail isn't emitted until flushed.
+    assert!(w.next_chunk().is_none());
+    w.flush().unwrap();
+    assert_eq!(&w.next_chunk().unwrap()[..], b"ijk");
+    assert!(w.next_chunk().is_none());
+    // Flushing with nothing pending emits nothing.
+    w.flush().unwrap();
+    assert!(w.next_chunk().is_none());
+  }
+}
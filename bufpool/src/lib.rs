@@ -1,28 +1,511 @@
+#![cfg_attr(feature = "read-buf", feature(core_io_borrowed_buf))]
+#![cfg_attr(all(test, feature = "read-buf"), feature(read_buf))]
+
+pub mod arena;
+pub mod batch;
 pub mod buf;
+pub mod builder;
+pub mod chunked;
+mod local;
+pub mod reaper;
+pub mod ring;
+pub mod shared;
+pub mod split;
+pub mod stats;
+pub mod strbuf;
+pub mod strided;
 
 use buf::Buf;
+use buf::BufReturnToken;
+use builder::BufPoolBuilder;
+use chunked::ChunkedWriter;
 use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
+use reaper::ReaperGuard;
+use shared::Interned;
+use shared::SharedBuf;
+use stats::PoolStats;
+use stats::SizeClassStats;
 use std::alloc::alloc;
+use std::alloc::alloc_zeroed;
+use std::alloc::dealloc;
 use std::alloc::Layout;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::collections::VecDeque;
-use std::mem::size_of;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::panic::RefUnwindSafe;
 use std::panic::UnwindSafe;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use strided::StridedBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocError {
+  /// The requested capacity exceeds the maximum allowed.
+  TooLarge { requested: usize, max: usize },
+  /// The system couldn't provide a slab of the requested capacity.
+  OutOfMemory { requested: usize },
+  /// The pool already has the maximum number of Bufs set by `with_max_live` allocated.
+  LimitReached { max: usize },
+}
+
+impl Display for AllocError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AllocError::TooLarge { requested, max } => write!(
+        f,
+        "requested capacity of {requested} bytes exceeds maximum of {max} bytes"
+      ),
+      AllocError::OutOfMemory { requested } => {
+        write!(f, "failed to allocate slab of {requested} bytes")
+      }
+      AllocError::LimitReached { max } => {
+        write!(f, "pool already has the maximum of {max} buffers allocated")
+      }
+    }
+  }
+}
+
+impl Error for AllocError {}
+
+/// Returned when a pool is configured with an invalid alignment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlignmentError {
+  Zero,
+  NotPowerOfTwo {
+    align: usize,
+  },
+  /// Slabs of mmap-backed pools can't be aligned beyond the page size.
+  ExceedsPageSize {
+    align: usize,
+    page_size: usize,
+  },
+}
+
+impl Display for AlignmentError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AlignmentError::Zero => write!(f, "alignment must not be zero"),
+      AlignmentError::NotPowerOfTwo { align } => {
+        write!(f, "alignment of {align} is not a power of two")
+      }
+      AlignmentError::ExceedsPageSize { align, page_size } => write!(
+        f,
+        "alignment of {align} exceeds the page size of {page_size} for an mmap-backed pool"
+      ),
+    }
+  }
+}
+
+impl Error for AlignmentError {}
+
+/// Where the slab for an allocation came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocSource {
+  /// Reused from the pool.
+  Pooled,
+  /// Newly allocated from the system, as the pool had none of that size.
+  Fresh,
+}
+
+/// A slab sitting in the pool, and whether all of its bytes are known to be zero (so `allocate_with_zeros` can skip zeroing it).
+#[derive(Clone, Copy)]
+struct RetainedSlab {
+  data: *mut u8,
+  zeroed: bool,
+}
 
 // TODO Benchmark parking_lot::Mutex<VecDeque<>> against crossbeam_channel and flume. Also consider one allocator per thread, which could waste a lot of memory but also be very quick.
-#[derive(Clone, Default)]
-struct BufPoolForSize(Arc<parking_lot::Mutex<VecDeque<*mut u8>>>);
+#[derive(Default)]
+struct BufPoolForSize {
+  retained: parking_lot::Mutex<VecDeque<RetainedSlab>>,
+  /// Buffers of this size currently allocated and not yet dropped.
+  live: AtomicUsize,
+  /// The highest `live` has ever been.
+  peak_live: AtomicUsize,
+  /// Allocations ever requested of this size, for `size_histogram`.
+  requested: AtomicU64,
+}
 
 unsafe impl Send for BufPoolForSize {}
 unsafe impl Sync for BufPoolForSize {}
-impl UnwindSafe for BufPoolForSize {}
-impl RefUnwindSafe for BufPoolForSize {}
+
+/// Where slabs are obtained from when the pool has none to reuse.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Backing {
+  Heap,
+  /// Each slab is its own anonymous mapping, so it bypasses the malloc arena and is returned to the OS as soon as it's freed.
+  #[cfg(unix)]
+  Mmap,
+  /// Each slab is a shared mapping of its own memfd, so its contents can be passed to `splice`/`sendfile` without a copy.
+  #[cfg(target_os = "linux")]
+  Memfd,
+  /// Slabs come from jemalloc, regardless of the global allocator.
+  #[cfg(feature = "jemalloc")]
+  Jemalloc,
+  /// Slabs come from mimalloc, regardless of the global allocator.
+  #[cfg(feature = "mimalloc")]
+  Mimalloc,
+}
+
+impl Backing {
+  /// Whether slabs are `mmap`ed directly, and therefore page aligned.
+  #[cfg(unix)]
+  fn is_mapped(self) -> bool {
+    match self {
+      Backing::Mmap => true,
+      #[cfg(target_os = "linux")]
+      Backing::Memfd => true,
+      _ => false,
+    }
+  }
+}
+
+/// Allocates from a specific allocator rather than the global one.
+#[cfg(any(feature = "jemalloc", feature = "mimalloc"))]
+fn allocate_from(
+  allocator: &impl std::alloc::GlobalAlloc,
+  layout: Layout,
+  zeroed: bool,
+) -> *mut u8 {
+  if zeroed {
+    unsafe { allocator.alloc_zeroed(layout) }
+  } else {
+    unsafe { allocator.alloc(layout) }
+  }
+}
+
+/// The largest capacity that can be allocated. Any larger and rounding up to the next power of two would exceed `isize::MAX`, the largest allocation Rust allows.
+const MAX_CAP: usize = 1 << (usize::BITS - 2);
+
+/// Transparent huge pages are 2 MiB on most platforms; smaller slabs can't be backed by one.
+#[cfg(target_os = "linux")]
+const HUGEPAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Called with a snapshot of the pool's stats when the bytes retained by the pool rise above `high_water`.
+#[derive(Clone)]
+pub(crate) struct PressureCallback {
+  pub(crate) high_water: usize,
+  pub(crate) callback: Arc<dyn Fn(PoolStats) + Send + Sync>,
+}
 
 struct BufPoolInner {
   align: usize,
-  #[cfg(not(feature = "no-pool"))]
+  backing: Backing,
+  transparent_hugepages: bool,
+  zero_on_free: bool,
+  prefault: bool,
+  max_retained_per_class: usize,
+  min_pool_class: usize,
+  thread_local_cache: bool,
+  max_live: usize,
+  max_alloc: usize,
+  /// Buffers of all sizes currently allocated and not yet dropped, for enforcing `max_live`.
+  live: AtomicUsize,
   sizes: Vec<BufPoolForSize>,
+  /// Total capacity of all retained slabs.
+  retained_bytes: AtomicUsize,
+  pressure: Option<PressureCallback>,
+  /// Cleared when the pressure callback fires, and set again once `retained_bytes` falls back to the high-water mark, so the callback fires once per crossing rather than on every release.
+  pressure_armed: AtomicBool,
+  interned: parking_lot::Mutex<Interned>,
+  /// Unique among all pools created by this process, to check that slabs are returned to the pool that allocated them.
+  #[cfg(feature = "origin-check")]
+  id: u64,
+  /// For memfd-backed pools, the memfd of each slab, keyed by the slab's address.
+  #[cfg(target_os = "linux")]
+  memfds: parking_lot::Mutex<HashMap<usize, std::os::fd::OwnedFd>>,
+}
+
+// Nothing that can panic runs while any of the pool's locks are held (in particular, slabs are freed and allocation failures are asserted after the lock is released), and parking_lot locks aren't poisoned, so a panic can't leave the pool locked or inconsistent.
+impl UnwindSafe for BufPoolInner {}
+impl RefUnwindSafe for BufPoolInner {}
+
+impl BufPoolInner {
+  /// If `zeroed`, the returned slab will be all zeros.
+  fn system_allocate_raw(&self, cap: usize, zeroed: bool) -> *mut u8 {
+    match self.backing {
+      Backing::Heap => {
+        let Ok(layout) = Layout::from_size_align(cap, self.align) else {
+          return std::ptr::null_mut();
+        };
+        if zeroed {
+          unsafe { alloc_zeroed(layout) }
+        } else {
+          unsafe { alloc(layout) }
+        }
+      }
+      // Anonymous mappings are always zero filled, so `zeroed` is free.
+      #[cfg(unix)]
+      Backing::Mmap => {
+        let ptr = unsafe {
+          libc::mmap(
+            std::ptr::null_mut(),
+            cap,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+          )
+        };
+        if ptr == libc::MAP_FAILED {
+          return std::ptr::null_mut();
+        };
+        #[cfg(target_os = "linux")]
+        if self.transparent_hugepages && cap >= HUGEPAGE_SIZE {
+          // This is only advice, so ignore any failure (e.g. THP is disabled).
+          unsafe { libc::madvise(ptr, cap, libc::MADV_HUGEPAGE) };
+        };
+        ptr.cast()
+      }
+      #[cfg(target_os = "linux")]
+      Backing::Memfd => {
+        use std::os::fd::AsRawFd;
+        use std::os::fd::FromRawFd;
+        use std::os::fd::OwnedFd;
+
+        let fd = unsafe { libc::memfd_create(c"bufpool".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+          return std::ptr::null_mut();
+        };
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), cap as libc::off_t) } != 0 {
+          return std::ptr::null_mut();
+        };
+        let ptr = unsafe {
+          libc::mmap(
+            std::ptr::null_mut(),
+            cap,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd.as_raw_fd(),
+            0,
+          )
+        };
+        if ptr == libc::MAP_FAILED {
+          return std::ptr::null_mut();
+        };
+        self.memfds.lock().insert(ptr as usize, fd);
+        ptr.cast()
+      }
+      #[cfg(feature = "jemalloc")]
+      Backing::Jemalloc => match Layout::from_size_align(cap, self.align) {
+        Ok(layout) => allocate_from(&tikv_jemallocator::Jemalloc, layout, zeroed),
+        Err(_) => std::ptr::null_mut(),
+      },
+      #[cfg(feature = "mimalloc")]
+      Backing::Mimalloc => match Layout::from_size_align(cap, self.align) {
+        Ok(layout) => allocate_from(&mimalloc::MiMalloc, layout, zeroed),
+        Err(_) => std::ptr::null_mut(),
+      },
+    }
+  }
+
+  unsafe fn system_deallocate_raw(&self, data: *mut u8, cap: usize) {
+    deallocate_raw(self.backing, self.align, data, cap);
+    #[cfg(target_os = "linux")]
+    if self.backing == Backing::Memfd {
+      // Release lock before closing.
+      let fd = self.memfds.lock().remove(&(data as usize));
+      drop(fd);
+    };
+  }
+
+  /// Returns a slab of `cap` bytes, and whether it's known to be all zeros. If `zeroed` and the pool has no slab to reuse, a zeroed slab is allocated.
+  fn take(self: &Arc<Self>, cap: usize, zeroed: bool) -> (RetainedSlab, AllocSource) {
+    if !cfg!(feature = "no-pool") {
+      if self.thread_local_cache {
+        if let Some(slab) = local::take(self, cap) {
+          return (slab, AllocSource::Pooled);
+        };
+      };
+      // Release lock ASAP.
+      let existing = self.sizes[cap.ilog2() as usize].retained.lock().pop_front();
+      if let Some(slab) = existing {
+        let retained_bytes = self.retained_bytes.fetch_sub(cap, Ordering::Relaxed) - cap;
+        self.rearm_pressure(retained_bytes);
+        return (slab, AllocSource::Pooled);
+      };
+    };
+    let slab = RetainedSlab {
+      data: self.system_allocate_raw(cap, zeroed),
+      zeroed,
+    };
+    if self.prefault && !slab.data.is_null() {
+      prefault(slab.data, cap);
+    };
+    #[cfg(feature = "origin-check")]
+    if !slab.data.is_null() {
+      ORIGINS.lock().insert(slab.data as usize, self.id);
+    };
+    (slab, AllocSource::Fresh)
+  }
+
+  /// Called when a Buf is dropped to return its slab to the pool.
+  fn release(self: &Arc<Self>, data: *mut u8, cap: usize) {
+    // Zero-capacity Bufs never took a slab.
+    if cap == 0 {
+      return;
+    };
+    #[cfg(feature = "origin-check")]
+    {
+      let origin = ORIGINS.lock().get(&(data as usize)).copied();
+      assert_eq!(
+        origin,
+        Some(self.id),
+        "slab at {data:p} is being returned to a pool that didn't allocate it"
+      );
+    };
+    let size = &self.sizes[cap.ilog2() as usize];
+    // A Buf created with `from_raw_parts` may not have been counted, so don't underflow.
+    for live in [&size.live, &self.live] {
+      let _ = live.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+    if cfg!(feature = "no-pool") || cap < self.min_pool_class {
+      unsafe { self.system_deallocate_raw(data, cap) };
+      return;
+    };
+    if self.zero_on_free {
+      unsafe { data.write_bytes(0, cap) };
+    };
+    let mut slab = RetainedSlab {
+      data,
+      zeroed: self.zero_on_free,
+    };
+    if self.thread_local_cache {
+      match local::put(self, cap, slab) {
+        None => return,
+        Some(uncached) => slab = uncached,
+      };
+    };
+    self.retain(cap, [slab]);
+  }
+
+  /// Adds slabs of `cap` bytes to the pool, freeing any beyond `max_retained_per_class`.
+  fn retain(
+    &self,
+    cap: usize,
+    slabs: impl IntoIterator<Item = RetainedSlab, IntoIter: ExactSizeIterator>,
+  ) {
+    let mut slabs = slabs.into_iter();
+    let size = &self.sizes[cap.ilog2() as usize];
+    let mut retained = size.retained.lock();
+    let n = slabs
+      .len()
+      .min(self.max_retained_per_class.saturating_sub(retained.len()));
+    // Count the slabs before they can be taken, so that `retained_bytes` can't underflow.
+    let retained_bytes = self.retained_bytes.fetch_add(n * cap, Ordering::Relaxed) + n * cap;
+    retained.extend(slabs.by_ref().take(n));
+    // Release lock before freeing.
+    drop(retained);
+    for slab in slabs {
+      unsafe { self.system_deallocate_raw(slab.data, cap) };
+    }
+    if let Some(pressure) = &self.pressure {
+      if n > 0
+        && retained_bytes > pressure.high_water
+        && self.pressure_armed.swap(false, Ordering::Relaxed)
+      {
+        (pressure.callback)(self.stats());
+      };
+    };
+  }
+
+  /// Frees retained slabs beyond the first `keep_per_class` of each size.
+  fn trim_to(&self, keep_per_class: usize) {
+    for (i, size) in self.sizes.iter().enumerate() {
+      // Release lock before freeing.
+      let retained = {
+        let mut retained = size.retained.lock();
+        if retained.len() <= keep_per_class {
+          continue;
+        };
+        retained.split_off(keep_per_class)
+      };
+      self
+        .retained_bytes
+        .fetch_sub(retained.len() << i, Ordering::Relaxed);
+      for slab in retained {
+        unsafe { self.system_deallocate_raw(slab.data, 1 << i) };
+      }
+    }
+    self.rearm_pressure(self.retained_bytes.load(Ordering::Relaxed));
+  }
+
+  /// Lets the pressure callback fire again once `retained_bytes` has fallen back to the high-water mark, whether through reuse or trimming.
+  fn rearm_pressure(&self, retained_bytes: usize) {
+    if let Some(pressure) = &self.pressure {
+      if retained_bytes <= pressure.high_water {
+        self.pressure_armed.store(true, Ordering::Relaxed);
+      };
+    };
+  }
+
+  fn stats(&self) -> PoolStats {
+    PoolStats {
+      classes: self
+        .sizes
+        .iter()
+        .enumerate()
+        .map(|(i, size)| SizeClassStats {
+          capacity: 1 << i,
+          retained: size.retained.lock().len(),
+          live: size.live.load(Ordering::Relaxed),
+          peak_live: size.peak_live.load(Ordering::Relaxed),
+        })
+        .collect(),
+    }
+  }
+}
+
+/// Frees a slab without needing its pool, e.g. for slabs cached by a thread after their pool was dropped. A memfd-backed slab's memfd isn't closed, as it's owned by the pool.
+unsafe fn deallocate_raw(backing: Backing, align: usize, data: *mut u8, cap: usize) {
+  // Unregister before freeing, as the address could be reused as soon as it's freed.
+  #[cfg(feature = "origin-check")]
+  ORIGINS.lock().remove(&(data as usize));
+  match backing {
+    Backing::Heap => dealloc(data, Layout::from_size_align(cap, align).unwrap()),
+    #[cfg(unix)]
+    Backing::Mmap => {
+      libc::munmap(data.cast(), cap);
+    }
+    #[cfg(target_os = "linux")]
+    Backing::Memfd => {
+      libc::munmap(data.cast(), cap);
+    }
+    #[cfg(feature = "jemalloc")]
+    Backing::Jemalloc => std::alloc::GlobalAlloc::dealloc(
+      &tikv_jemallocator::Jemalloc,
+      data,
+      Layout::from_size_align(cap, align).unwrap(),
+    ),
+    #[cfg(feature = "mimalloc")]
+    Backing::Mimalloc => std::alloc::GlobalAlloc::dealloc(
+      &mimalloc::MiMalloc,
+      data,
+      Layout::from_size_align(cap, align).unwrap(),
+    ),
+  }
+}
+
+impl Drop for BufPoolInner {
+  fn drop(&mut self) {
+    for i in 0..self.sizes.len() {
+      let retained = std::mem::take(self.sizes[i].retained.get_mut());
+      for slab in retained {
+        unsafe { self.system_deallocate_raw(slab.data, 1 << i) };
+      }
+    }
+  }
 }
 
 #[derive(Clone)]
@@ -31,52 +514,254 @@ pub struct BufPool {
 }
 
 impl BufPool {
+  /// Returns a builder for combining several options, e.g. alignment, retention limits, and zeroing, that the `with_*` constructors only provide one at a time.
+  pub fn builder() -> BufPoolBuilder {
+    BufPoolBuilder::new()
+  }
+
   pub fn with_alignment(align: usize) -> Self {
-    assert!(align > 0);
-    assert!(align.is_power_of_two());
-    Self {
-      inner: Arc::new(BufPoolInner {
-        align,
-        #[cfg(not(feature = "no-pool"))]
-        sizes: (0..(size_of::<usize>() * 8))
-          .map(|_| Default::default())
-          .collect(),
-      }),
-    }
+    Self::builder().alignment(align).build()
+  }
+
+  /// Like `with_alignment`, but returns an error instead of panicking if `align` is invalid.
+  pub fn try_with_alignment(align: usize) -> Result<Self, AlignmentError> {
+    Self::builder().alignment(align).try_build()
   }
 
   pub fn new() -> Self {
-    Self::with_alignment(size_of::<usize>())
+    Self::builder().build()
+  }
+
+  /// Aligns all slabs to the system page size, as required for direct I/O (e.g. `O_DIRECT`).
+  #[cfg(unix)]
+  pub fn with_page_alignment() -> Self {
+    Self::with_alignment(page_size())
+  }
+
+  /// Decodes standard base64 (with padding) directly into a Buf from this pool.
+  #[cfg(feature = "base64")]
+  pub fn from_base64(&self, input: &[u8]) -> Result<Buf, base64::DecodeError> {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::DecodeSliceError;
+    use base64::Engine;
+    let mut buf = self.allocate_uninitialised(base64::decoded_len_estimate(input.len()));
+    match STANDARD.decode_slice(input, buf.as_mut_slice()) {
+      Ok(n) => {
+        buf.truncate(n);
+        Ok(buf)
+      }
+      Err(DecodeSliceError::DecodeError(e)) => Err(e),
+      Err(DecodeSliceError::OutputSliceTooSmall) => unreachable!(),
+    }
+  }
+
+  /// Returns an `io::Write` that splits everything written to it into pooled Bufs of `chunk` bytes.
+  pub fn chunked_writer(&self, chunk: usize) -> ChunkedWriter {
+    ChunkedWriter::new(self, chunk)
   }
 
-  fn system_allocate_raw(&self, cap: usize) -> *mut u8 {
-    unsafe { alloc(Layout::from_size_align(cap, self.inner.align).unwrap()) }
+  /// Like `mmap_backed`, but each slab is backed by its own memfd, available from `Buf::as_fd`. This allows a buffer's contents to be sent with `splice` or `sendfile` without copying through userspace. Note that each slab uses a file descriptor for as long as it's allocated or retained.
+  #[cfg(target_os = "linux")]
+  pub fn memfd_backed() -> Self {
+    Self::builder().memfd_backed().build()
   }
 
-  /// NOTE: This provides a Buf that can grow to `cap`, but has an initial length of zero. Use `allocate_with_zeros` to return something equivalent to `vec![0u8; cap]`.
-  /// `cap` can safely be zero, but it will still cause an allocation of one byte due to rounding.
+  /// Slabs are allocated from jemalloc, independent of the global allocator. Direct I/O buffers often benefit from a different allocator than the rest of the application.
+  #[cfg(feature = "jemalloc")]
+  pub fn jemalloc_backed() -> Self {
+    Self::builder().jemalloc_backed().build()
+  }
+
+  /// Like `jemalloc_backed`, but using mimalloc.
+  #[cfg(feature = "mimalloc")]
+  pub fn mimalloc_backed() -> Self {
+    Self::builder().mimalloc_backed().build()
+  }
+
+  /// Returns the process-wide pool for `align`, creating it on first use. Like `BUFPOOL`, this lets unrelated parts of an application share retained slabs instead of each creating their own pool.
+  pub fn shared(align: usize) -> Self {
+    SHARED
+      .lock()
+      .entry(align)
+      .or_insert_with(|| Self::with_alignment(align))
+      .clone()
+  }
+
+  /// Touches every page of newly allocated slabs before they're handed out, so that first access doesn't page fault. This moves the cost of faulting pages in to allocation, for services where predictable access latency matters more.
+  pub fn with_prefault() -> Self {
+    Self::builder().prefault(true).build()
+  }
+
+  /// Refuses any allocation of more than `max` bytes, as a pool-wide alternative to `allocate_bounded` that guards against untrusted size prefixes requesting huge buffers. `try_allocate` returns `AllocError::TooLarge` for such requests, and `allocate` panics. This also applies when a Buf grows.
+  pub fn with_max_alloc(max: usize) -> Self {
+    Self::builder().max_alloc(max).build()
+  }
+
+  /// Limits how many Bufs can be allocated from the pool at once, for admission control in memory-bound services. Once `max` Bufs are in use, `try_allocate` returns `AllocError::LimitReached` (and `allocate` panics) until one is dropped. Zero-capacity Bufs aren't counted, and growing an existing Buf is never refused.
+  pub fn with_max_live(max: usize) -> Self {
+    Self::builder().max_live(max).build()
+  }
+
+  /// Calls `f` with the pool's stats when the total capacity of retained slabs rises above `high_water` bytes, e.g. to apply backpressure or `trim` the pool. It's called from whichever thread returns the slab that crosses the threshold, and only fires again once the total has fallen back to `high_water`, as slabs are reused or trimmed.
+  pub fn with_pressure_callback(
+    high_water: usize,
+    f: impl Fn(PoolStats) + Send + Sync + 'static,
+  ) -> Self {
+    Self::builder().pressure_callback(high_water, f).build()
+  }
+
+  /// Retain at most `max_retained_per_class` slabs of each size; any more are freed when returned to the pool.
+  pub fn with_limits(max_retained_per_class: usize) -> Self {
+    Self::builder()
+      .max_retained_per_class(max_retained_per_class)
+      .build()
+  }
+
+  /// Slabs are allocated with `mmap` instead of from the heap, and are page aligned. This is intended for very large buffers, which would otherwise fragment the malloc arena; freed slabs (e.g. via `trim`) are unmapped and returned to the OS immediately.
+  #[cfg(unix)]
+  pub fn mmap_backed() -> Self {
+    Self::builder().mmap_backed().build()
+  }
+
+  /// NOTE: This provides a Buf with room for `cap` bytes (it will grow if more are appended), but has an initial length of zero. Use `allocate_with_zeros` to return something equivalent to `vec![0u8; cap]`.
+  /// `cap` can be zero, in which case no slab is taken from the pool until something is written to the Buf.
   pub fn allocate(&self, cap: usize) -> Buf {
-    // This will round `0` to `1`.
-    let cap = cap.next_power_of_two();
+    self.allocate_slab(cap, false, true).0
+  }
 
-    #[cfg(not(feature = "no-pool"))]
-    let data = if let Some(data) = self.inner.sizes[cap.ilog2() as usize].0.lock().pop_front() {
-      data
+  /// Allocates a slab to move an existing Buf's contents to, which isn't refused by `max_live`, as the existing slab is about to be returned.
+  pub(crate) fn allocate_replacement(&self, cap: usize) -> Buf {
+    self.allocate_slab(cap, false, false).0
+  }
+
+  /// Same as `allocate`, but named for what it provides: a Buf whose `capacity()` is `cap` rounded up to the next power of two (e.g. 1024 for 1000).
+  pub fn allocate_at_least(&self, cap: usize) -> Buf {
+    self.allocate(cap)
+  }
+
+  /// Allocates a Buf with room for at least `cap` bytes from the size class for `typical`, the size that buffers for this use usually grow to (e.g. 1024 plus a header), so that filling one up to the typical size doesn't move it to a larger slab via `reserve`. The class for `cap` is used instead if it's larger, or if `typical` exceeds the pool's `max_alloc`. `size_histogram` counts the request against `cap`'s class, so it still reflects the sizes actually requested rather than the hints.
+  pub fn allocate_hinted(&self, typical: usize, cap: usize) -> Buf {
+    match self.try_allocate_slab_hinted(typical, cap, false, true) {
+      Ok((buf, ..)) => buf,
+      Err(err) => panic!("{err}"),
+    }
+  }
+
+  /// Like `allocate`, but also returns whether the slab was reused from the pool or freshly allocated, e.g. to assert exact reuse behaviour in tests. Slabs of the same size are reused in the order they were returned to the pool, except that with `thread_local_cache`, the slab most recently cached by the current thread is reused first. A zero `cap` is reported as `Pooled`, as it doesn't allocate.
+  pub fn allocate_tracked(&self, cap: usize) -> (Buf, AllocSource) {
+    let (buf, _, source) = self.allocate_slab(cap, false, true);
+    (buf, source)
+  }
+
+  /// Like `allocate`, but returns an error instead of panicking if the system can't provide a slab or the pool's `max_live` has been reached, for callers that must not abort under memory pressure.
+  pub fn try_allocate(&self, cap: usize) -> Result<Buf, AllocError> {
+    Ok(self.try_allocate_slab(cap, false, true)?.0)
+  }
+
+  /// Returns an error instead of allocating if `cap` exceeds `max`, which is useful when `cap` comes from untrusted input (e.g. a length prefix).
+  pub fn allocate_bounded(&self, cap: usize, max: usize) -> Result<Buf, AllocError> {
+    if cap > max {
+      return Err(AllocError::TooLarge {
+        requested: cap,
+        max,
+      });
+    };
+    Ok(self.allocate(cap))
+  }
+
+  /// Also returns whether the entire slab is known to be zeroed, and where it came from. If `limited`, this fails once `max_live` Bufs are allocated.
+  fn allocate_slab(&self, cap: usize, zeroed: bool, limited: bool) -> (Buf, bool, AllocSource) {
+    match self.try_allocate_slab(cap, zeroed, limited) {
+      Ok(res) => res,
+      Err(err) => panic!("{err}"),
+    }
+  }
+
+  fn try_allocate_slab(
+    &self,
+    cap: usize,
+    zeroed: bool,
+    limited: bool,
+  ) -> Result<(Buf, bool, AllocSource), AllocError> {
+    self.try_allocate_slab_hinted(0, cap, zeroed, limited)
+  }
+
+  /// Like `try_allocate_slab`, but uses the size class for `typical` if it's larger than `cap`'s and within the pool's limits. Only `cap` is validated and counted by `size_histogram`.
+  fn try_allocate_slab_hinted(
+    &self,
+    typical: usize,
+    cap: usize,
+    zeroed: bool,
+    limited: bool,
+  ) -> Result<(Buf, bool, AllocSource), AllocError> {
+    if cap > self.inner.max_alloc {
+      return Err(AllocError::TooLarge {
+        requested: cap,
+        max: self.inner.max_alloc,
+      });
+    };
+    let Some(class) = cap.checked_next_power_of_two().filter(|&c| c <= MAX_CAP) else {
+      return Err(AllocError::TooLarge {
+        requested: cap,
+        max: MAX_CAP,
+      });
+    };
+    self.inner.sizes[class.ilog2() as usize]
+      .requested
+      .fetch_add(1, Ordering::Relaxed);
+    let hinted = Some(typical)
+      .filter(|&t| t > cap && t <= self.inner.max_alloc)
+      .and_then(usize::checked_next_power_of_two)
+      .filter(|&c| c <= MAX_CAP);
+    if cap == 0 && hinted.is_none() {
+      // Use a dangling but aligned pointer, like an empty Vec does. `reserve` replaces it with a real slab on the first write.
+      let buf = Buf {
+        data: self.inner.align as *mut u8,
+        len: 0,
+        cap: 0,
+        pool: self.clone(),
+        arena: None,
+      };
+      return Ok((buf, true, AllocSource::Pooled));
+    };
+    let cap = hinted.unwrap_or(class);
+
+    let max = if limited {
+      self.inner.max_live
     } else {
-      self.system_allocate_raw(cap)
+      usize::MAX
+    };
+    if self
+      .inner
+      .live
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+        (n < max).then_some(n + 1)
+      })
+      .is_err()
+    {
+      return Err(AllocError::LimitReached { max });
     };
-    #[cfg(feature = "no-pool")]
-    let data = self.system_allocate_raw(cap);
+
+    let (RetainedSlab { data, zeroed }, source) = self.inner.take(cap, zeroed);
 
     // Failed allocations may return null.
-    assert!(!data.is_null());
+    if data.is_null() {
+      self.inner.live.fetch_sub(1, Ordering::Relaxed);
+      return Err(AllocError::OutOfMemory { requested: cap });
+    };
+
+    let size = &self.inner.sizes[cap.ilog2() as usize];
+    let live = size.live.fetch_add(1, Ordering::Relaxed) + 1;
+    size.peak_live.fetch_max(live, Ordering::Relaxed);
 
-    Buf {
+    let buf = Buf {
       data,
       len: 0,
       cap,
       pool: self.clone(),
-    }
+      arena: None,
+    };
+    Ok((buf, zeroed, source))
   }
 
   pub fn allocate_from_data(&self, data: impl AsRef<[u8]>) -> Buf {
@@ -85,13 +770,24 @@ impl BufPool {
     buf
   }
 
+  /// `len` is only a capacity hint: an iterator yielding fewer items leaves the Buf partially filled, and one yielding more grows it via `reserve` instead of panicking.
   pub fn allocate_from_iter(&self, data: impl IntoIterator<Item = u8>, len: usize) -> Buf {
     let mut buf = self.allocate(len);
     buf.extend(data);
     buf
   }
 
-  /// The returned Buf will have a length equal to the capacity, filled with uninitialised bytes.
+  /// Allocates a zeroed buffer of `rows` rows, each `stride` bytes apart, of which the first `row_len` bytes are accessible via `StridedBuf::row`. Panics if `row_len` exceeds `stride`.
+  pub fn allocate_strided(&self, row_len: usize, rows: usize, stride: usize) -> StridedBuf {
+    assert!(
+      row_len <= stride,
+      "row length {row_len} exceeds stride {stride}"
+    );
+    let len = rows.checked_mul(stride).expect("capacity overflow");
+    StridedBuf::new(self.allocate_with_zeros(len), row_len, rows, stride)
+  }
+
+  /// The returned Buf will have a length of `len`, filled with uninitialised bytes.
   pub fn allocate_uninitialised(&self, len: usize) -> Buf {
     let mut buf = self.allocate(len);
     unsafe { buf.set_len(len) };
@@ -104,9 +800,977 @@ impl BufPool {
     buf
   }
 
+  /// Fills the Buf by repeating `pattern`; the last repetition is cut short if `len` isn't a multiple of the pattern's length. Panics if `pattern` is empty.
+  pub fn allocate_with_pattern(&self, pattern: &[u8], len: usize) -> Buf {
+    assert!(!pattern.is_empty());
+    let mut buf = self.allocate_uninitialised(len);
+    for chunk in buf.chunks_mut(pattern.len()) {
+      chunk.copy_from_slice(&pattern[..chunk.len()]);
+    }
+    buf
+  }
+
+  /// This skips zeroing when the slab is already known to be zeroed: if it was freshly allocated, or was zeroed when returned to a pool with `zero_on_free` enabled.
   pub fn allocate_with_zeros(&self, len: usize) -> Buf {
-    self.allocate_with_fill(0, len)
+    let (mut buf, zeroed, _) = self.allocate_slab(len, true, true);
+    unsafe { buf.set_len(len) };
+    if !zeroed {
+      buf.fill(0);
+    };
+    buf
+  }
+
+  /// Returns a SharedBuf with a copy of `data`, or a clone of a previously interned one with identical contents if it's still in use, so that repetitive payloads share one slab. Interned buffers aren't kept alive by the pool, and are returned to it once the last clone is dropped.
+  pub fn intern(&self, data: &[u8]) -> SharedBuf {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    let hash = hasher.finish();
+    let find = |candidates: &[Arc<Buf>]| candidates.iter().find(|b| b.as_slice() == data).cloned();
+    let candidates = self.inner.interned.lock().candidates(hash);
+    if let Some(buf) = find(&candidates) {
+      return SharedBuf {
+        range: 0..buf.len(),
+        buf,
+      };
+    };
+    drop(candidates);
+    // Allocate outside the lock, then check again in case another thread interned the same data in the meantime.
+    let new = Arc::new(self.allocate_from_data(data));
+    let mut interned = self.inner.interned.lock();
+    let candidates = interned.candidates(hash);
+    let buf = match find(&candidates) {
+      Some(buf) => buf,
+      None => {
+        interned.insert(hash, &new);
+        new.clone()
+      }
+    };
+    // Release lock before dropping `candidates` and `new`, which may return slabs to the pool.
+    drop(interned);
+    SharedBuf {
+      range: 0..buf.len(),
+      buf,
+    }
+  }
+
+  /// Panics with details if any retained slab is null, misaligned, or retained more than once (e.g. after a double free), for checking the pool's invariants in property tests and fuzzers.
+  #[cfg(feature = "test-util")]
+  pub fn assert_invariants(&self) {
+    let mut seen = HashMap::<usize, usize>::new();
+    for (i, size) in self.inner.sizes.iter().enumerate() {
+      // Copy the pointers out first, so that nothing panics while the lock is held.
+      let ptrs = size
+        .retained
+        .lock()
+        .iter()
+        .map(|s| s.data as usize)
+        .collect::<Vec<_>>();
+      let cap = 1usize << i;
+      for ptr in ptrs {
+        assert_ne!(ptr, 0, "retained slab of class {cap} is null");
+        assert_eq!(
+          ptr & (self.inner.align - 1),
+          0,
+          "retained slab {ptr:#x} of class {cap} isn't aligned to {}",
+          self.inner.align
+        );
+        if let Some(other) = seen.insert(ptr, cap) {
+          panic!("slab {ptr:#x} is retained twice, by class {other} and class {cap}");
+        };
+      }
+    }
+  }
+
+  /// Calls `f(capacity, retained)` for each size class with retained slabs, without allocating like `stats` does, e.g. for periodic logging. Each class's lock is released before `f` is called, so `f` can safely use the pool, although the counts may then be stale.
+  pub fn inspect(&self, mut f: impl FnMut(usize, usize)) {
+    for (i, size) in self.inner.sizes.iter().enumerate() {
+      let count = size.retained.lock().len();
+      if count > 0 {
+        f(1 << i, count);
+      };
+    }
+  }
+
+  /// Concatenates `parts` with `sep` between each, into a new Buf sized up front, like `[&str]::join`.
+  pub fn join(&self, parts: &[Buf], sep: &[u8]) -> Buf {
+    let len = parts
+      .iter()
+      .map(|p| p.len())
+      .sum::<usize>()
+      .saturating_add(sep.len() * parts.len().saturating_sub(1));
+    let mut buf = self.allocate(len);
+    for (i, part) in parts.iter().enumerate() {
+      if i > 0 {
+        buf.extend_from_slice(sep);
+      };
+      buf.extend_from_slice(part);
+    }
+    buf
+  }
+
+  /// Reads up to `len` bytes from `fd` at `offset` into a new Buf, without zeroing it first. Short reads are retried until `len` bytes are read or EOF is reached, and the Buf is truncated to the bytes read. For direct I/O, use a page-aligned pool.
+  #[cfg(unix)]
+  pub fn pread(&self, fd: std::os::fd::RawFd, offset: u64, len: usize) -> std::io::Result<Buf> {
+    let mut buf = self.allocate_uninitialised(len);
+    let mut filled = 0;
+    while filled < len {
+      let n = unsafe {
+        libc::pread(
+          fd,
+          buf.as_mut_ptr().add(filled).cast(),
+          len - filled,
+          (offset + filled as u64) as libc::off_t,
+        )
+      };
+      match n {
+        0 => break,
+        n if n > 0 => filled += n as usize,
+        _ => {
+          let err = std::io::Error::last_os_error();
+          if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+          };
+        }
+      };
+    }
+    buf.truncate(filled);
+    Ok(buf)
+  }
+
+  /// Reads up to `len` bytes from the start of the file at `path` into a new Buf, without zeroing it first. If the file is shorter than `len`, the Buf is truncated to the bytes read.
+  #[cfg(feature = "tokio")]
+  pub async fn read_file(
+    &self,
+    path: impl AsRef<std::path::Path>,
+    len: usize,
+  ) -> std::io::Result<Buf> {
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = self.allocate_uninitialised(len);
+    let mut filled = 0;
+    while filled < len {
+      match file.read(&mut buf[filled..]).await {
+        Ok(0) => break,
+        Ok(n) => filled += n,
+        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+        Err(e) => return Err(e),
+      };
+    }
+    buf.truncate(filled);
+    Ok(buf)
+  }
+
+  /// How many allocations have ever been requested from each size class, indexed like `PoolStats::classes` (a zero-length request counts towards the first class, and requests refused for being too large aren't counted). Unlike `stats`, which reports current occupancy, this is cumulative, so it shows which classes dominate the workload when tuning retention limits.
+  pub fn size_histogram(&self) -> [u64; usize::BITS as usize] {
+    let mut hist = [0; usize::BITS as usize];
+    for (h, size) in hist.iter_mut().zip(self.inner.sizes.iter()) {
+      *h = size.requested.load(Ordering::Relaxed);
+    }
+    hist
+  }
+
+  pub fn stats(&self) -> PoolStats {
+    self.inner.stats()
+  }
+
+  /// Frees all retained slabs, resets each class's `peak_live` to its current `live` count, and zeroes the `size_histogram` counters, so that a test sharing a pool (e.g. `BUFPOOL`) with others in the same process can start from a clean state. Buffers still in use keep being counted as live, so `max_live` stays enforced; drop them first for fully zeroed stats.
+  pub fn reset_for_tests(&self) {
+    self.trim();
+    for size in self.inner.sizes.iter() {
+      size
+        .peak_live
+        .store(size.live.load(Ordering::Relaxed), Ordering::Relaxed);
+      size.requested.store(0, Ordering::Relaxed);
+    }
+  }
+
+  /// Returns a slab taken with `Buf::into_return_token` to its pool. If the pool has since been dropped, the slab is leaked.
+  ///
+  /// # Safety
+  ///
+  /// `data` must be the pointer returned alongside `token`, and nothing may use it afterwards.
+  pub unsafe fn return_slab(token: BufReturnToken, data: *mut u8) {
+    if let Some(inner) = token.pool.upgrade() {
+      inner.release(data, token.cap);
+    };
+  }
+
+  /// How many slabs of the size class for `cap` are currently retained by the pool.
+  pub fn retained_count(&self, cap: usize) -> usize {
+    self.inner.sizes[cap.next_power_of_two().ilog2() as usize]
+      .retained
+      .lock()
+      .len()
+  }
+
+  /// Starts a background thread that calls `trim_to(keep_per_class)` every `interval`, until the returned guard is dropped or the pool itself is dropped.
+  pub fn spawn_reaper(&self, interval: Duration, keep_per_class: usize) -> ReaperGuard {
+    ReaperGuard::spawn(Arc::downgrade(&self.inner), interval, keep_per_class)
+  }
+
+  /// Frees all slabs currently retained by the pool. Buffers that are still in use are unaffected, and will return to the pool as usual when dropped.
+  ///
+  /// With `thread_local_cache`, only the calling thread's cache is flushed and freed; slabs cached by other threads aren't freed until those threads return them to the pool.
+  pub fn trim(&self) {
+    self.trim_to(0);
+  }
+
+  /// Like `trim`, but keeps up to `keep_per_class` retained slabs of each size. As with `trim`, only the calling thread's `thread_local_cache` is flushed.
+  pub fn trim_to(&self, keep_per_class: usize) {
+    local::flush(&self.inner);
+    self.inner.trim_to(keep_per_class);
+  }
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+  unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Writes a zero to every page of the slab, so that they're all resident before it's handed out. Zeroed slabs stay zeroed, and the contents of other slabs are uninitialised anyway.
+fn prefault(data: *mut u8, cap: usize) {
+  #[cfg(unix)]
+  let page = page_size();
+  #[cfg(not(unix))]
+  let page = 4096;
+  for offset in (0..cap).step_by(page) {
+    unsafe { data.add(offset).write_volatile(0) };
   }
 }
 
-pub static BUFPOOL: Lazy<BufPool> = Lazy::new(|| BufPool::new());
+impl Default for BufPool {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+static MAX_TRY_FROM_LEN: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Sets the maximum length of a slice that `Buf::try_from` will copy into `BUFPOOL`. Unlimited by default.
+///
+/// The limit is process-wide, so it also applies to `Buf::try_from` calls made by other crates. Libraries should use `BufPool::allocate_bounded` with their own limit instead of setting this.
+pub fn set_max_try_from_len(max: usize) {
+  MAX_TRY_FROM_LEN.store(max, Ordering::Relaxed);
+}
+
+pub(crate) fn max_try_from_len() -> usize {
+  MAX_TRY_FROM_LEN.load(Ordering::Relaxed)
+}
+
+/// The id of the pool that allocated each live or retained slab, keyed by the slab's address.
+#[cfg(feature = "origin-check")]
+static ORIGINS: Lazy<parking_lot::Mutex<HashMap<usize, u64>>> = Lazy::new(Default::default);
+
+#[cfg(feature = "origin-check")]
+static NEXT_POOL_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+#[cfg(feature = "origin-check")]
+pub(crate) fn next_pool_id() -> u64 {
+  NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+static SHARED: Lazy<parking_lot::Mutex<HashMap<usize, BufPool>>> = Lazy::new(Default::default);
+
+/// Returned by `configure_global` once `BUFPOOL` has been created or configured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlreadyInitialized;
+
+impl Display for AlreadyInitialized {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "global pool has already been initialized")
+  }
+}
+
+impl Error for AlreadyInitialized {}
+
+/// Set by `configure_global`, or to the default when `BUFPOOL` is first used, whichever happens first.
+static GLOBAL_ALIGN: OnceCell<usize> = OnceCell::new();
+
+#[cfg(not(all(unix, feature = "page-aligned-global")))]
+fn default_global_align() -> usize {
+  // The same as `BufPool::new`.
+  std::mem::size_of::<usize>()
+}
+
+#[cfg(all(unix, feature = "page-aligned-global"))]
+fn default_global_align() -> usize {
+  page_size()
+}
+
+/// Sets the alignment of `BUFPOOL`, e.g. to page-align it from `main` for direct I/O without enabling the `page-aligned-global` feature. This must be called before `BUFPOOL` is first used, and only once; otherwise it returns an error and the alignment is unchanged. Panics if `align` isn't a power of two.
+pub fn configure_global(align: usize) -> Result<(), AlreadyInitialized> {
+  assert!(align.is_power_of_two());
+  GLOBAL_ALIGN.set(align).map_err(|_| AlreadyInitialized)
+}
+
+pub static BUFPOOL: Lazy<BufPool> =
+  Lazy::new(|| BufPool::with_alignment(*GLOBAL_ALIGN.get_or_init(default_global_align)));
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn max_alloc_refuses_only_above_ceiling() {
+    let pool = BufPool::with_max_alloc(1000);
+    assert_eq!(pool.try_allocate(999).unwrap().capacity(), 1024);
+    assert_eq!(pool.try_allocate(1000).unwrap().capacity(), 1024);
+    assert_eq!(
+      pool.try_allocate(1001).unwrap_err(),
+      AllocError::TooLarge {
+        requested: 1001,
+        max: 1000
+      }
+    );
+    assert_eq!(
+      pool.try_allocate(usize::MAX).unwrap_err(),
+      AllocError::TooLarge {
+        requested: usize::MAX,
+        max: 1000
+      }
+    );
+  }
+
+  #[test]
+  fn max_alloc_growth_is_limited_to_bytes_needed() {
+    let pool = BufPool::with_max_alloc(1000);
+    let mut buf = pool.allocate(300);
+    buf.extend_from_slice(&[1; 520]);
+    assert_eq!(buf.len(), 520);
+    assert_eq!(buf.capacity(), 1024);
+  }
+
+  #[test]
+  #[should_panic(expected = "exceeds maximum of 1000 bytes")]
+  fn max_alloc_growth_above_ceiling_panics() {
+    let pool = BufPool::with_max_alloc(1000);
+    let mut buf = pool.allocate(300);
+    buf.extend_from_slice(&[1; 1001]);
+  }
+
+  #[test]
+  fn try_allocate_returns_errors_for_huge_sizes() {
+    let pool = BufPool::new();
+    for cap in [
+      (1 << (usize::BITS - 1)) + 1,
+      1 << (usize::BITS - 1),
+      usize::MAX,
+    ] {
+      assert_eq!(
+        pool.try_allocate(cap).unwrap_err(),
+        AllocError::TooLarge {
+          requested: cap,
+          max: MAX_CAP
+        }
+      );
+    }
+    // The system can't provide this much, so this exercises the allocator failing rather than the size check.
+    assert_eq!(
+      pool.try_allocate(MAX_CAP).unwrap_err(),
+      AllocError::OutOfMemory { requested: MAX_CAP }
+    );
+    assert_eq!(pool.stats().class(MAX_CAP).live, 0);
+    pool.allocate(1);
+  }
+
+  #[test]
+  fn size_histogram_counts_requests_per_class() {
+    let pool = BufPool::with_max_alloc(1 << 20);
+    let bufs = [0, 1, 100, 128, 1000, 1 << 20].map(|cap| pool.allocate(cap));
+    drop(bufs);
+    pool.allocate(100);
+    assert!(pool.try_allocate((1 << 20) + 1).is_err());
+    assert!(pool.try_allocate(usize::MAX).is_err());
+    let hist = pool.size_histogram();
+    let mut expected = [0; usize::BITS as usize];
+    expected[0] = 2;
+    expected[7] = 3;
+    expected[10] = 1;
+    expected[20] = 1;
+    assert_eq!(hist, expected);
+  }
+
+  #[test]
+  fn intern_shares_identical_data() {
+    let pool = BufPool::new();
+    let a = pool.intern(b"payload");
+    let b = pool.intern(b"payload");
+    let c = pool.intern(b"other");
+    assert!(SharedBuf::ptr_eq(&a, &b));
+    assert!(!SharedBuf::ptr_eq(&a, &c));
+    assert_eq!(&*b, b"payload");
+    assert_eq!(&*c, b"other");
+    assert_eq!(pool.stats().class(7).live, 2);
+    drop((a, b, c));
+    assert_eq!(pool.stats().class(7).live, 0);
+    // Nothing keeps the old slab alive, so this is a new interned Buf.
+    assert_eq!(&*pool.intern(b"payload"), b"payload");
+  }
+
+  #[test]
+  fn intern_doesnt_grow_without_bound() {
+    let pool = BufPool::new();
+    for i in 0..10_000u32 {
+      pool.intern(&i.to_le_bytes());
+    }
+    assert!(pool.inner.interned.lock().len() <= 32);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn pressure_callback_fires_on_each_crossing() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let f = fired.clone();
+    let pool = BufPool::with_pressure_callback(1024 * 3, move |stats| {
+      assert_eq!(stats.class(1024).retained, 4);
+      f.fetch_add(1, Ordering::Relaxed);
+    });
+    let bufs = (0..5).map(|_| pool.allocate(1024)).collect::<Vec<_>>();
+    drop(bufs);
+    // It fired when the fourth slab was retained, and not again for the fifth.
+    assert_eq!(fired.load(Ordering::Relaxed), 1);
+    // Reusing slabs brings the total back down without trimming, which rearms it.
+    let bufs = (0..3).map(|_| pool.allocate(1024)).collect::<Vec<_>>();
+    assert_eq!(fired.load(Ordering::Relaxed), 1);
+    let mut bufs = bufs.into_iter();
+    drop(bufs.next());
+    drop(bufs.next());
+    assert_eq!(fired.load(Ordering::Relaxed), 2);
+  }
+
+  #[cfg(all(unix, not(feature = "no-pool")))]
+  #[test]
+  fn mmap_backed_large_buffers_read_and_write() {
+    let pool = BufPool::mmap_backed();
+    let cap = 8 * 1024 * 1024;
+    let mut buf = pool.allocate(cap);
+    buf.extend((0..cap).map(|i| (i % 251) as u8));
+    assert!(buf.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+    let ptr = buf.as_ptr();
+    drop(buf);
+    assert_eq!(pool.stats().class(cap).retained, 1);
+
+    // The slab is reused from the pool, and is still writable.
+    let mut buf = pool.allocate(cap);
+    assert_eq!(buf.as_ptr(), ptr);
+    buf.extend_from_slice(&[7; 4096]);
+    assert_eq!(&buf[..], &[7; 4096]);
+    drop(buf);
+    pool.trim();
+    assert_eq!(pool.stats().class(cap).retained, 0);
+  }
+
+  #[test]
+  fn allocate_with_zeros_is_zeroed_whether_or_not_it_skips_zeroing() {
+    for zero_on_free in [false, true] {
+      let pool = BufPool::builder().zero_on_free(zero_on_free).build();
+      // Freshly allocated, so known to be zero.
+      let mut buf = pool.allocate_with_zeros(4096);
+      assert!(buf.iter().all(|&b| b == 0));
+      buf.fill(0xff);
+      let ptr = buf.as_ptr();
+      drop(buf);
+
+      // Reused, and either zeroed when returned or zeroed now.
+      let buf = pool.allocate_with_zeros(4096);
+      assert_eq!(buf.as_ptr(), ptr);
+      assert_eq!(buf.len(), 4096);
+      assert!(buf.iter().all(|&b| b == 0));
+    }
+  }
+
+  #[test]
+  fn peak_live_records_high_water_mark() {
+    let pool = BufPool::new();
+    let bufs = (0..5).map(|_| pool.allocate(65536)).collect::<Vec<_>>();
+    drop(bufs);
+    let stats = pool.stats();
+    assert_eq!(stats.class(65536).live, 0);
+    assert_eq!(stats.class(65536).peak_live, 5);
+    // Fewer at once later doesn't lower it.
+    drop(pool.allocate(65536));
+    assert_eq!(pool.stats().class(65536).peak_live, 5);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn pool_is_usable_after_a_panic_during_allocation() {
+    let pool = BufPool::with_max_live(1);
+    let held = pool.allocate(64);
+    let res = std::panic::catch_unwind(|| pool.allocate(64));
+    assert!(res.is_err());
+    let res = std::panic::catch_unwind(|| pool.allocate(usize::MAX));
+    assert!(res.is_err());
+
+    // Neither panic left a lock held or a slab counted as live.
+    drop(held);
+    let buf = pool.allocate(64);
+    assert_eq!(pool.stats().class(64).live, 1);
+    drop(buf);
+    assert_eq!(pool.stats().class(64).retained, 1);
+    pool.trim();
+    assert_eq!(pool.stats().class(64).retained, 0);
+  }
+
+  #[test]
+  fn allocate_bounded_refuses_only_above_max() {
+    let pool = BufPool::new();
+    assert_eq!(pool.allocate_bounded(100, 100).unwrap().capacity(), 128);
+    assert_eq!(
+      pool.allocate_bounded(101, 100).unwrap_err(),
+      AllocError::TooLarge {
+        requested: 101,
+        max: 100
+      }
+    );
+  }
+
+  // This is the only test that uses `Buf::try_from`, as the limit is process-wide.
+  #[test]
+  fn try_from_slice_respects_global_limit() {
+    set_max_try_from_len(4);
+    assert_eq!(&Buf::try_from(&b"abcd"[..]).unwrap()[..], b"abcd");
+    assert_eq!(
+      Buf::try_from(&b"abcde"[..]).unwrap_err(),
+      AllocError::TooLarge {
+        requested: 5,
+        max: 4
+      }
+    );
+    set_max_try_from_len(usize::MAX);
+    assert_eq!(Buf::try_from(&b"abcde"[..]).unwrap().len(), 5);
+  }
+
+  #[cfg(feature = "tokio")]
+  #[test]
+  fn read_file_reads_up_to_len() {
+    let path = std::env::temp_dir().join(format!("bufpool-read-file-{}", std::process::id()));
+    std::fs::write(&path, b"hello, world").unwrap();
+    let rt = tokio::runtime::Builder::new_current_thread()
+      .build()
+      .unwrap();
+    let pool = BufPool::new();
+    let buf = rt.block_on(pool.read_file(&path, 5)).unwrap();
+    assert_eq!(&buf[..], b"hello");
+    // The file is shorter than `len`, so the Buf is truncated to what was read.
+    let buf = rt.block_on(pool.read_file(&path, 100)).unwrap();
+    assert_eq!(&buf[..], b"hello, world");
+    assert_eq!(buf.capacity(), 128);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn shared_returns_the_same_pool_per_alignment() {
+    let a = BufPool::shared(4096);
+    let b = BufPool::shared(4096);
+    assert!(Arc::ptr_eq(&a.inner, &b.inner));
+    assert!(!Arc::ptr_eq(&a.inner, &BufPool::shared(512).inner));
+    assert!(a.allocate(1).is_aligned_to(4096));
+  }
+
+  #[test]
+  fn allocate_with_pattern_tiles_the_pattern() {
+    let pool = BufPool::new();
+    let buf = pool.allocate_with_pattern(b"\xde\xad\xbe\xef", 8);
+    assert_eq!(&buf[..], b"\xde\xad\xbe\xef\xde\xad\xbe\xef");
+    // The last repetition is cut short.
+    let buf = pool.allocate_with_pattern(b"abc", 7);
+    assert_eq!(&buf[..], b"abcabca");
+    let buf = pool.allocate_with_pattern(b"abc", 2);
+    assert_eq!(&buf[..], b"ab");
+    assert!(pool.allocate_with_pattern(b"abc", 0).is_empty());
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn allocate_tracked_reports_fresh_then_pooled() {
+    let pool = BufPool::new();
+    let (a, source) = pool.allocate_tracked(64);
+    assert_eq!(source, AllocSource::Fresh);
+    let (b, source) = pool.allocate_tracked(64);
+    assert_eq!(source, AllocSource::Fresh);
+    let (a_ptr, b_ptr) = (a.as_ptr(), b.as_ptr());
+    drop(a);
+    drop(b);
+    // Reused in the order they were returned.
+    let (a, source) = pool.allocate_tracked(64);
+    assert_eq!(source, AllocSource::Pooled);
+    assert_eq!(a.as_ptr(), a_ptr);
+    let (b, source) = pool.allocate_tracked(64);
+    assert_eq!(source, AllocSource::Pooled);
+    assert_eq!(b.as_ptr(), b_ptr);
+    assert_eq!(pool.allocate_tracked(64).1, AllocSource::Fresh);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn allocate_tracked_reuses_most_recent_with_thread_local_cache() {
+    let pool = BufPool::builder().thread_local_cache(true).build();
+    let a = pool.allocate(64);
+    let b = pool.allocate(64);
+    let b_ptr = b.as_ptr();
+    drop(a);
+    drop(b);
+    let (b, source) = pool.allocate_tracked(64);
+    assert_eq!(source, AllocSource::Pooled);
+    assert_eq!(b.as_ptr(), b_ptr);
+  }
+
+  #[test]
+  fn allocate_from_iter_treats_len_as_a_hint() {
+    let pool = BufPool::new();
+    let buf = pool.allocate_from_iter(b"abc".iter().copied(), 8);
+    assert_eq!(&buf[..], b"abc");
+    assert_eq!(buf.capacity(), 8);
+    // More items than `len` grow the Buf instead of panicking.
+    let buf = pool.allocate_from_iter(0..20, 8);
+    assert_eq!(buf.len(), 20);
+    assert!(buf.iter().copied().eq(0..20));
+    // An exact count doesn't grow it.
+    let buf = pool.allocate_from_iter(0..8, 8);
+    assert_eq!(buf.capacity(), 8);
+  }
+
+  #[cfg(all(unix, feature = "page-aligned-global"))]
+  #[test]
+  fn global_pool_is_page_aligned() {
+    assert!(BUFPOOL.allocate(1).is_aligned_to(page_size()));
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn allocate_zero_doesnt_use_the_pool_until_written() {
+    let pool = BufPool::new();
+    drop(pool.allocate(1));
+    assert_eq!(pool.stats().class(1).retained, 1);
+
+    let buf = pool.allocate(0);
+    assert_eq!(buf.capacity(), 0);
+    assert_eq!(buf.as_slice(), b"");
+    // Nothing was taken from the pool or allocated.
+    let stats = pool.stats();
+    assert!(stats.classes.iter().all(|c| c.live == 0));
+    assert_eq!(stats.class(1).retained, 1);
+    drop(buf);
+    assert!(pool.stats().classes.iter().all(|c| c.live == 0));
+
+    let mut buf = pool.allocate(0);
+    buf.push(1);
+    let stats = pool.stats();
+    assert_eq!(stats.class(1).live, 1);
+    assert_eq!(stats.class(1).retained, 0);
+    assert_eq!(&buf[..], &[1]);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn retained_count_counts_dropped_buffers() {
+    let pool = BufPool::new();
+    assert_eq!(pool.retained_count(1000), 0);
+    drop((0..7).map(|_| pool.allocate(1000)).collect::<Vec<_>>());
+    assert_eq!(pool.retained_count(1000), 7);
+    // Any size in the class gives the same count, and other classes are unaffected.
+    assert_eq!(pool.retained_count(1024), 7);
+    assert_eq!(pool.retained_count(512), 0);
+    let _buf = pool.allocate(1000);
+    assert_eq!(pool.retained_count(1000), 6);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn min_pool_class_frees_smaller_slabs() {
+    let pool = BufPool::builder().min_pool_class(16).build();
+    drop(pool.allocate(8));
+    assert_eq!(pool.retained_count(8), 0);
+    assert_eq!(pool.stats().class(8).live, 0);
+    drop(pool.allocate(16));
+    assert_eq!(pool.retained_count(16), 1);
+
+    // Slabs replaced when growing are freed too.
+    let mut buf = pool.allocate(4);
+    buf.extend_from_slice(b"abcde");
+    assert_eq!(buf.capacity(), 8);
+    assert_eq!(pool.retained_count(4), 0);
+    assert_eq!(pool.stats().class(4).live, 0);
+  }
+
+  #[cfg(feature = "jemalloc")]
+  #[test]
+  fn jemalloc_backed_allocates_and_frees() {
+    let pool = BufPool::jemalloc_backed();
+    let mut buf = pool.allocate(4096);
+    buf.extend_from_slice(&[3; 4096]);
+    assert!(buf.iter().all(|&b| b == 3));
+    drop(buf);
+    assert_eq!(pool.retained_count(4096), 1);
+    // Freed through jemalloc.
+    pool.trim();
+    assert_eq!(pool.retained_count(4096), 0);
+    assert_eq!(pool.allocate_tracked(4096).1, AllocSource::Fresh);
+  }
+
+  #[cfg(feature = "mimalloc")]
+  #[test]
+  fn mimalloc_backed_allocates_and_frees() {
+    let pool = BufPool::mimalloc_backed();
+    let mut buf = pool.allocate(4096);
+    buf.extend_from_slice(&[3; 4096]);
+    assert!(buf.iter().all(|&b| b == 3));
+    drop(buf);
+    assert_eq!(pool.retained_count(4096), 1);
+    // Freed through mimalloc.
+    pool.trim();
+    assert_eq!(pool.retained_count(4096), 0);
+    assert_eq!(pool.allocate_tracked(4096).1, AllocSource::Fresh);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn allocate_uninitialised_sets_len_without_zeroing() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate_uninitialised(100);
+    assert_eq!(buf.len(), 100);
+    assert_eq!(buf.capacity(), 128);
+    buf.fill(0xab);
+    let ptr = buf.as_ptr();
+    drop(buf);
+    // The reused slab still holds the old bytes, as nothing was zeroed.
+    let buf = pool.allocate_uninitialised(100);
+    assert_eq!(buf.as_ptr(), ptr);
+    assert!(buf.iter().all(|&b| b == 0xab));
+    assert!(pool.allocate_uninitialised(0).is_empty());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn pread_reads_at_an_offset() {
+    use std::os::fd::AsRawFd;
+
+    let path = std::env::temp_dir().join(format!("bufpool-pread-{}", std::process::id()));
+    std::fs::write(&path, b"0123456789abcdef").unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let pool = BufPool::new();
+    let buf = pool.pread(file.as_raw_fd(), 10, 4).unwrap();
+    assert_eq!(&buf[..], b"abcd");
+    // Reading past the end of the file is truncated to what's there.
+    let buf = pool.pread(file.as_raw_fd(), 12, 100).unwrap();
+    assert_eq!(&buf[..], b"cdef");
+    assert!(pool.pread(file.as_raw_fd(), 16, 4).unwrap().is_empty());
+    assert!(pool.pread(-1, 0, 4).is_err());
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn return_token_returns_slab_from_another_thread() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(64);
+    buf.extend_from_slice(b"hello");
+    let (token, data) = buf.into_return_token();
+    // Raw pointers aren't Send, so carry the address across as an integer.
+    let addr = data as usize;
+    std::thread::spawn(move || unsafe { BufPool::return_slab(token, addr as *mut u8) })
+      .join()
+      .unwrap();
+    assert_eq!(pool.retained_count(64), 1);
+    let (buf, source) = pool.allocate_tracked(64);
+    assert_eq!(source, AllocSource::Pooled);
+    assert_eq!(buf.as_slice().as_ptr(), data as *const u8);
+  }
+
+  #[test]
+  fn allocate_at_least_rounds_capacity_up() {
+    let pool = BufPool::new();
+    assert_eq!(pool.allocate_at_least(1000).capacity(), 1024);
+    assert_eq!(pool.allocate_at_least(1024).capacity(), 1024);
+    assert_eq!(pool.allocate_at_least(1).capacity(), 1);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn reset_for_tests_empties_pool_and_zeroes_stats() {
+    let pool = BufPool::new();
+    let bufs: Vec<_> = [1, 64, 1000, 4096].map(|cap| pool.allocate(cap)).into();
+    drop(bufs);
+    assert_eq!(pool.retained_count(1000), 1);
+    pool.reset_for_tests();
+    for class in pool.stats().classes {
+      assert_eq!(class.retained, 0);
+      assert_eq!(class.live, 0);
+      assert_eq!(class.peak_live, 0);
+    }
+    assert_eq!(pool.size_histogram(), [0; usize::BITS as usize]);
+  }
+
+  #[test]
+  fn reset_for_tests_keeps_counting_buffers_in_use() {
+    let pool = BufPool::with_max_live(1);
+    drop(pool.allocate(16));
+    let held = pool.allocate(64);
+    pool.reset_for_tests();
+    let stats = pool.stats();
+    assert_eq!(stats.class(64).live, 1);
+    assert_eq!(stats.class(64).peak_live, 1);
+    assert_eq!(stats.class(16).peak_live, 0);
+    // The held Buf still counts towards `max_live`.
+    assert!(pool.try_allocate(64).is_err());
+    drop(held);
+    assert_eq!(pool.stats().class(64).live, 0);
+    assert_eq!(pool.try_allocate(64).unwrap().capacity(), 64);
+  }
+
+  #[test]
+  fn join_handles_empty_single_and_unseparated_parts() {
+    let pool = BufPool::new();
+    let part = |s: &[u8]| {
+      let mut buf = pool.allocate(s.len());
+      buf.extend_from_slice(s);
+      buf
+    };
+    assert!(pool.join(&[], b", ").is_empty());
+    assert_eq!(pool.join(&[part(b"one")], b", ").as_slice(), b"one");
+    let parts = [part(b"a"), part(b""), part(b"bc")];
+    assert_eq!(pool.join(&parts, b", ").as_slice(), b"a, , bc");
+    let joined = pool.join(&parts, b"");
+    assert_eq!(joined.as_slice(), b"abc");
+    assert_eq!(joined.capacity(), 4);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn inspect_reports_only_classes_with_retained_slabs() {
+    let pool = BufPool::new();
+    let mut seen = Vec::new();
+    pool.inspect(|cap, count| seen.push((cap, count)));
+    assert!(seen.is_empty());
+    drop([pool.allocate(16), pool.allocate(16), pool.allocate(1000)]);
+    let _live = pool.allocate(64);
+    pool.inspect(|cap, count| seen.push((cap, count)));
+    assert_eq!(seen, [(16, 2), (1024, 1)]);
+  }
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn inspect_callback_can_use_pool() {
+    let pool = BufPool::new();
+    drop(pool.allocate(32));
+    pool.inspect(|cap, _| drop(pool.allocate(cap)));
+    assert_eq!(pool.retained_count(32), 1);
+  }
+
+  #[cfg(all(unix, not(feature = "no-pool")))]
+  #[test]
+  fn prefaulted_buffer_is_fully_readable_and_writable() {
+    let page = page_size();
+    for pool in [
+      BufPool::with_prefault(),
+      BufPool::builder().prefault(true).mmap_backed().build(),
+    ] {
+      let cap = page * 4;
+      let mut buf = pool.allocate_with_zeros(cap);
+      assert!(buf.iter().all(|&b| b == 0));
+      for (i, b) in buf.iter_mut().enumerate() {
+        *b = (i % 251) as u8;
+      }
+      assert!(buf.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+      drop(buf);
+      // A reused slab is handed out as is.
+      let (buf, source) = pool.allocate_tracked(cap);
+      assert_eq!(source, AllocSource::Pooled);
+      assert_eq!(buf.capacity(), cap);
+    }
+  }
+
+  #[test]
+  fn try_with_alignment_validates_align() {
+    assert_eq!(
+      BufPool::try_with_alignment(0).err(),
+      Some(AlignmentError::Zero)
+    );
+    assert_eq!(
+      BufPool::try_with_alignment(48).err(),
+      Some(AlignmentError::NotPowerOfTwo { align: 48 })
+    );
+    let pool = BufPool::try_with_alignment(64).unwrap();
+    let buf = pool.allocate(8);
+    assert_eq!(buf.as_slice().as_ptr() as usize % 64, 0);
+  }
+
+  #[cfg(feature = "test-util")]
+  proptest::proptest! {
+    #[test]
+    fn random_allocate_and_drop_keeps_invariants(
+      ops in proptest::collection::vec((proptest::bool::ANY, 0usize..5000, 0usize..64), 1..200),
+    ) {
+      let pool = BufPool::builder().max_retained_per_class(8).build();
+      let mut live = Vec::new();
+      for (alloc, cap, idx) in ops {
+        if alloc || live.is_empty() {
+          let mut buf = pool.allocate(cap);
+          buf.extend_zeroed(cap);
+          live.push(buf);
+        } else {
+          drop(live.swap_remove(idx % live.len()));
+        };
+        pool.assert_invariants();
+      }
+      drop(live);
+      pool.assert_invariants();
+      pool.trim();
+      pool.assert_invariants();
+      proptest::prop_assert!(pool.stats().classes.iter().all(|c| c.retained == 0 && c.live == 0));
+    }
+  }
+
+  #[test]
+  fn max_live_refuses_until_a_buf_is_dropped() {
+    let pool = BufPool::with_max_live(2);
+    let a = pool.try_allocate(64).unwrap();
+    let mut b = pool.try_allocate(4096).unwrap();
+    assert_eq!(
+      pool.try_allocate(64).unwrap_err(),
+      AllocError::LimitReached { max: 2 }
+    );
+    // Zero-capacity Bufs aren't counted, and growing is never refused.
+    let _empty = pool.try_allocate(0).unwrap();
+    b.extend_zeroed(8192);
+    assert_eq!(b.capacity(), 8192);
+
+    drop(a);
+    let c = pool.try_allocate(64).unwrap();
+    assert_eq!(c.capacity(), 64);
+    assert!(pool.try_allocate(64).is_err());
+  }
+
+  #[test]
+  fn allocate_hinted_uses_class_of_typical_size() {
+    let pool = BufPool::builder().max_alloc(1 << 20).build();
+    // Typically 1024 plus a header, but only 10 bytes are needed up front.
+    let mut buf = pool.allocate_hinted(1024 + 16, 10);
+    assert_eq!(buf.capacity(), 2048);
+    buf.extend_zeroed(1024 + 16);
+    assert_eq!(buf.capacity(), 2048);
+    // A larger `cap` still gets a class that covers it.
+    assert_eq!(pool.allocate_hinted(100, 3000).capacity(), 4096);
+    // Even an empty Buf gets a slab of the typical size.
+    assert_eq!(pool.allocate_hinted(100, 0).capacity(), 128);
+    assert_eq!(pool.allocate_hinted(0, 0).capacity(), 0);
+    // A hint over `max_alloc` is ignored rather than refused.
+    assert_eq!(pool.allocate_hinted(usize::MAX, 10).capacity(), 16);
+
+    // Only the actual requests are counted by the histogram.
+    let histogram = pool.size_histogram();
+    assert_eq!(histogram[4], 2);
+    assert_eq!(histogram[12], 1);
+    assert_eq!(histogram[0], 2);
+    assert_eq!(histogram.iter().sum::<u64>(), 5);
+  }
+
+  #[test]
+  #[should_panic(expected = "requested capacity of 2000 bytes exceeds maximum of 1000 bytes")]
+  fn allocate_hinted_still_refuses_cap_over_max_alloc() {
+    let pool = BufPool::with_max_alloc(1000);
+    let _ = pool.allocate_hinted(100, 2000);
+  }
+}
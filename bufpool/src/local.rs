@@ -0,0 +1,177 @@
+use crate::deallocate_raw;
+use crate::Backing;
+use crate::BufPoolInner;
+use crate::RetainedSlab;
+use std::cell::RefCell;
+use std::mem;
+use std::sync::Arc;
+use std::sync::Weak;
+
+/// How many slabs of each size the current thread caches for each pool before returning them to the pool as a batch.
+pub(crate) const LOCAL_CACHE_SLABS: usize = 8;
+
+/// Holds only a weak reference, so that caching slabs doesn't keep a pool alive. If the pool is dropped, its cached slabs are freed directly, using the backing and alignment recorded here.
+struct LocalEntry {
+  pool: Weak<BufPoolInner>,
+  backing: Backing,
+  align: usize,
+  cap: usize,
+  slabs: Vec<RetainedSlab>,
+}
+
+impl LocalEntry {
+  fn is_for(&self, pool: &Arc<BufPoolInner>, cap: usize) -> bool {
+    self.cap == cap && Weak::as_ptr(&self.pool) == Arc::as_ptr(pool)
+  }
+}
+
+impl Drop for LocalEntry {
+  fn drop(&mut self) {
+    match self.pool.upgrade() {
+      Some(pool) => pool.retain(self.cap, self.slabs.drain(..)),
+      None => {
+        for slab in self.slabs.drain(..) {
+          unsafe { deallocate_raw(self.backing, self.align, slab.data, self.cap) };
+        }
+      }
+    };
+  }
+}
+
+thread_local! {
+  // Entries are dropped (and therefore returned to their pools) when the thread exits.
+  static CACHE: RefCell<Vec<LocalEntry>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Removes the entries of pools that have been dropped, so that a long-lived thread doesn't accumulate entries for every pool it has ever used. Their slabs are freed once the returned entries are dropped.
+fn take_dead(cache: &mut Vec<LocalEntry>) -> Vec<LocalEntry> {
+  if cache.iter().all(|e| e.pool.strong_count() > 0) {
+    return Vec::new();
+  };
+  let (live, dead) = mem::take(cache)
+    .into_iter()
+    .partition(|e| e.pool.strong_count() > 0);
+  *cache = live;
+  dead
+}
+
+/// Takes a slab of `cap` bytes for `pool` from the current thread's cache, without locking.
+pub(crate) fn take(pool: &Arc<BufPoolInner>, cap: usize) -> Option<RetainedSlab> {
+  let (slab, dead) = CACHE
+    .try_with(|cache| {
+      let Ok(mut cache) = cache.try_borrow_mut() else {
+        return (None, Vec::new());
+      };
+      let dead = take_dead(&mut cache);
+      let slab = cache
+        .iter_mut()
+        .find(|e| e.is_for(pool, cap))
+        .and_then(|e| e.slabs.pop());
+      (slab, dead)
+    })
+    .unwrap_or_default();
+  // Free slabs outside the borrow.
+  drop(dead);
+  slab
+}
+
+/// Caches a slab in the current thread, returning the cached batch to the pool first if it's full. If the cache can't be used (e.g. during thread exit), the slab is handed back.
+pub(crate) fn put(
+  pool: &Arc<BufPoolInner>,
+  cap: usize,
+  slab: RetainedSlab,
+) -> Option<RetainedSlab> {
+  let mut slab = Some(slab);
+  let (full, dead) = CACHE
+    .try_with(|cache| {
+      // This can already be borrowed if a pressure callback drops a Buf while we're returning a batch.
+      let Ok(mut cache) = cache.try_borrow_mut() else {
+        return (None, Vec::new());
+      };
+      let dead = take_dead(&mut cache);
+      let idx = match cache.iter().position(|e| e.is_for(pool, cap)) {
+        Some(idx) => idx,
+        None => {
+          cache.push(LocalEntry {
+            pool: Arc::downgrade(pool),
+            backing: pool.backing,
+            align: pool.align,
+            cap,
+            slabs: Vec::with_capacity(LOCAL_CACHE_SLABS),
+          });
+          cache.len() - 1
+        }
+      };
+      let entry = &mut cache[idx];
+      let full = (entry.slabs.len() == LOCAL_CACHE_SLABS).then(|| mem::take(&mut entry.slabs));
+      entry.slabs.push(slab.take().unwrap());
+      (full, dead)
+    })
+    .unwrap_or_default();
+  drop(dead);
+  // Return the batch outside the borrow, as it may call the pressure callback.
+  if let Some(full) = full {
+    pool.retain(cap, full);
+  };
+  slab
+}
+
+/// Returns all slabs the current thread has cached for `pool` to the pool.
+pub(crate) fn flush(pool: &Arc<BufPoolInner>) {
+  let mine = CACHE
+    .try_with(|cache| {
+      let Ok(mut cache) = cache.try_borrow_mut() else {
+        return Vec::new();
+      };
+      let (mine, others) = mem::take(&mut *cache)
+        .into_iter()
+        .partition(|e| Weak::as_ptr(&e.pool) == Arc::as_ptr(pool));
+      *cache = others;
+      mine
+    })
+    .unwrap_or_default();
+  // Dropping the entries returns their slabs, which must happen outside the borrow.
+  drop(mine);
+}
+
+// Every test here checks that cached slabs end up retained, which never happens with `no-pool`.
+#[cfg(all(test, not(feature = "no-pool")))]
+mod tests {
+  use super::*;
+
+  use crate::BufPool;
+  use std::sync::Arc;
+  use std::thread;
+
+  #[test]
+  fn cached_slabs_reach_the_pool_when_the_thread_exits() {
+    let pool = BufPool::builder().thread_local_cache(true).build();
+    let p = pool.clone();
+    thread::spawn(move || {
+      let bufs = (0..LOCAL_CACHE_SLABS * 2 + 3)
+        .map(|_| p.allocate(64))
+        .collect::<Vec<_>>();
+      drop(bufs);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(pool.retained_count(64), LOCAL_CACHE_SLABS * 2 + 3);
+  }
+
+  #[test]
+  fn cache_doesnt_keep_pool_alive() {
+    let pool = BufPool::builder().thread_local_cache(true).build();
+    drop(pool.allocate(64));
+    let weak = Arc::downgrade(&pool.inner);
+    drop(pool);
+    assert!(weak.upgrade().is_none());
+    // The next use of the cache frees the dead pool's slabs and entry.
+    let other = BufPool::builder().thread_local_cache(true).build();
+    drop(other.allocate(64));
+    CACHE.with(|cache| {
+      let cache = cache.borrow();
+      assert_eq!(cache.len(), 1);
+      assert_eq!(Weak::as_ptr(&cache[0].pool), Arc::as_ptr(&other.inner));
+    });
+  }
+}
@@ -0,0 +1,94 @@
+use crate::BufPool;
+use crate::BufPoolInner;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Weak;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Stops the reaper thread started by `BufPool::spawn_reaper` when dropped. Dropping waits for the thread to exit.
+pub struct ReaperGuard {
+  stop: Option<mpsc::Sender<()>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl ReaperGuard {
+  pub(crate) fn spawn(
+    inner: Weak<BufPoolInner>,
+    interval: Duration,
+    keep_per_class: usize,
+  ) -> Self {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = thread::spawn(move || {
+      while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+        // Only hold a strong reference while trimming, so the reaper doesn't keep the pool alive.
+        let Some(inner) = inner.upgrade() else {
+          break;
+        };
+        BufPool { inner }.trim_to(keep_per_class);
+      }
+    });
+    Self {
+      stop: Some(stop),
+      thread: Some(thread),
+    }
+  }
+}
+
+impl Drop for ReaperGuard {
+  fn drop(&mut self) {
+    // Disconnecting the channel wakes the thread immediately.
+    drop(self.stop.take());
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::BufPool;
+  use std::sync::Arc;
+  use std::thread;
+  use std::time::Duration;
+  use std::time::Instant;
+
+  #[cfg(not(feature = "no-pool"))]
+  #[test]
+  fn trims_after_the_interval_and_stops_when_dropped() {
+    let pool = BufPool::new();
+    let retained = || pool.stats().class(64).retained;
+    drop((0..5).map(|_| pool.allocate(64)).collect::<Vec<_>>());
+    assert_eq!(retained(), 5);
+
+    let guard = pool.spawn_reaper(Duration::from_millis(10), 1);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while retained() > 1 {
+      assert!(Instant::now() < deadline, "reaper didn't trim");
+      thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(retained(), 1);
+
+    // Dropping the guard waits for the thread to exit, so nothing is trimmed afterwards.
+    drop(guard);
+    drop((0..5).map(|_| pool.allocate(64)).collect::<Vec<_>>());
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(retained(), 5);
+  }
+
+  #[test]
+  fn doesnt_keep_the_pool_alive() {
+    let pool = BufPool::new();
+    let guard = pool.spawn_reaper(Duration::from_millis(1), 0);
+    let inner = Arc::downgrade(&pool.inner);
+    drop(pool);
+    // The reaper only holds a strong reference briefly while trimming.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while inner.strong_count() > 0 {
+      assert!(Instant::now() < deadline, "reaper kept the pool alive");
+      thread::sleep(Duration::from_millis(1));
+    }
+    drop(guard);
+  }
+}
@@ -0,0 +1,165 @@
+use crate::buf::Buf;
+use crate::BufPool;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+/// A fixed-capacity byte FIFO with wraparound, backed by a pooled slab that returns to the pool on drop. Useful for stream reassembly, where data is produced and consumed in the same thread.
+pub struct RingBuf {
+  buf: Buf,
+  head: usize,
+  len: usize,
+}
+
+impl RingBuf {
+  pub fn new(pool: &BufPool, cap: usize) -> Self {
+    Self {
+      buf: pool.allocate_with_zeros(cap),
+      head: 0,
+      len: 0,
+    }
+  }
+
+  /// How many bytes can currently be read.
+  pub fn available(&self) -> usize {
+    self.len
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.buf.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn is_full(&self) -> bool {
+    self.len == self.capacity()
+  }
+
+  pub fn pop_front(&mut self) -> Option<u8> {
+    if self.is_empty() {
+      return None;
+    };
+    let b = self.buf[self.head];
+    self.head = (self.head + 1) % self.capacity();
+    self.len -= 1;
+    Some(b)
+  }
+
+  /// Returns false without writing anything if the buffer is full.
+  pub fn push_back(&mut self, b: u8) -> bool {
+    if self.is_full() {
+      return false;
+    };
+    let tail = (self.head + self.len) % self.capacity();
+    self.buf[tail] = b;
+    self.len += 1;
+    true
+  }
+}
+
+impl Read for RingBuf {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = buf.len().min(self.len);
+    // The readable bytes may wrap around, so copy in up to two parts.
+    let first = n.min(self.capacity() - self.head);
+    buf[..first].copy_from_slice(&self.buf[self.head..self.head + first]);
+    buf[first..n].copy_from_slice(&self.buf[..n - first]);
+    if n > 0 {
+      self.head = (self.head + n) % self.capacity();
+    };
+    self.len -= n;
+    Ok(n)
+  }
+}
+
+/// Writes as much as fits, returning `Ok(0)` if the buffer is full.
+impl Write for RingBuf {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = buf.len().min(self.capacity() - self.len);
+    if n == 0 {
+      return Ok(0);
+    };
+    let tail = (self.head + self.len) % self.capacity();
+    // The free space may wrap around, so copy in up to two parts.
+    let first = n.min(self.capacity() - tail);
+    self.buf[tail..tail + first].copy_from_slice(&buf[..first]);
+    self.buf[..n - first].copy_from_slice(&buf[first..n]);
+    self.len += n;
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::ring::RingBuf;
+  use crate::BufPool;
+  use std::collections::VecDeque;
+  use std::io::Read;
+  use std::io::Write;
+
+  #[test]
+  fn wraps_around_when_written_and_read() {
+    let pool = BufPool::new();
+    let mut ring = RingBuf::new(&pool, 8);
+    assert_eq!(ring.write(b"abcdef").unwrap(), 6);
+    let mut out = [0u8; 4];
+    assert_eq!(ring.read(&mut out).unwrap(), 4);
+    assert_eq!(&out, b"abcd");
+    // This write wraps past the end of the slab.
+    assert_eq!(ring.write(b"ghijkl").unwrap(), 6);
+    assert!(ring.is_full());
+    let mut out = [0u8; 16];
+    assert_eq!(ring.read(&mut out).unwrap(), 8);
+    assert_eq!(&out[..8], b"efghijkl");
+    assert!(ring.is_empty());
+    assert_eq!(ring.read(&mut out).unwrap(), 0);
+  }
+
+  #[test]
+  fn refuses_writes_when_full() {
+    let pool = BufPool::new();
+    let mut ring = RingBuf::new(&pool, 4);
+    assert_eq!(ring.write(b"abcdef").unwrap(), 4);
+    assert!(ring.is_full());
+    assert_eq!(ring.available(), 4);
+    assert_eq!(ring.write(b"g").unwrap(), 0);
+    assert!(!ring.push_back(b'g'));
+    assert_eq!(ring.pop_front(), Some(b'a'));
+    assert!(ring.push_back(b'g'));
+    let mut out = [0u8; 4];
+    ring.read_exact(&mut out).unwrap();
+    assert_eq!(&out, b"bcdg");
+  }
+
+  #[test]
+  fn interleaved_byte_and_slice_operations_keep_order() {
+    let pool = BufPool::new();
+    let mut ring = RingBuf::new(&pool, 5);
+    let mut expected = VecDeque::new();
+    let mut next = 0u8;
+    for round in 0..50 {
+      // Vary the mix so that the head and tail wrap at different points.
+      for _ in 0..(round % 4) {
+        if ring.push_back(next) {
+          expected.push_back(next);
+        };
+        next = next.wrapping_add(1);
+      }
+      let data = [next, next.wrapping_add(1)];
+      let n = ring.write(&data).unwrap();
+      expected.extend(&data[..n]);
+      next = next.wrapping_add(2);
+      assert_eq!(ring.pop_front(), expected.pop_front());
+      let mut out = [0u8; 2];
+      let n = ring.read(&mut out[..round % 3]).unwrap();
+      assert_eq!(&out[..n], expected.drain(..n).collect::<Vec<_>>());
+      assert_eq!(ring.available(), expected.len());
+    }
+  }
+}
@@ -0,0 +1,93 @@
+use crate::buf::Buf;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::Weak;
+
+/// Buffers created by `BufPool::intern`, keyed by a hash of their contents. Entries are weak, so interned buffers are still returned to the pool once unused.
+#[derive(Default)]
+pub(crate) struct Interned {
+  entries: HashMap<u64, Vec<Weak<Buf>>>,
+  /// Dead entries are swept from the whole map once it grows to this many hashes, so that it doesn't grow without bound when fed many distinct payloads that are never looked up again.
+  sweep_at: usize,
+}
+
+impl Interned {
+  /// Returns the live buffers with `hash`. They must be dropped after the lock on this is released, as one may be the last reference to its Buf, and returning a slab to the pool can call the pressure callback.
+  pub(crate) fn candidates(&mut self, hash: u64) -> Vec<Arc<Buf>> {
+    let Some(entries) = self.entries.get_mut(&hash) else {
+      return Vec::new();
+    };
+    let candidates = entries.iter().filter_map(Weak::upgrade).collect::<Vec<_>>();
+    entries.retain(|e| e.strong_count() > 0);
+    if entries.is_empty() {
+      self.entries.remove(&hash);
+    };
+    candidates
+  }
+
+  pub(crate) fn insert(&mut self, hash: u64, buf: &Arc<Buf>) {
+    if self.entries.len() >= self.sweep_at {
+      self.entries.retain(|_, entries| {
+        entries.retain(|e| e.strong_count() > 0);
+        !entries.is_empty()
+      });
+      // Sweeping again only once the map has doubled keeps the cost amortised constant per insert.
+      self.sweep_at = (self.entries.len() * 2).max(16);
+    };
+    self
+      .entries
+      .entry(hash)
+      .or_default()
+      .push(Arc::downgrade(buf));
+  }
+
+  #[cfg(test)]
+  pub(crate) fn len(&self) -> usize {
+    self.entries.len()
+  }
+}
+
+/// An immutable, reference-counted view of a Buf's contents, or a range of them. Clones share the same slab, which is returned to the pool once the last clone is dropped. Created with `BufPool::intern` or `Buf::freeze_range`.
+#[derive(Clone)]
+pub struct SharedBuf {
+  pub(crate) buf: Arc<Buf>,
+  pub(crate) range: Range<usize>,
+}
+
+impl SharedBuf {
+  pub fn as_slice(&self) -> &[u8] {
+    &self.buf[self.range.clone()]
+  }
+
+  /// Whether both share the same slab, e.g. because they were interned from identical data.
+  pub fn ptr_eq(a: &SharedBuf, b: &SharedBuf) -> bool {
+    Arc::ptr_eq(&a.buf, &b.buf)
+  }
+}
+
+impl AsRef<[u8]> for SharedBuf {
+  fn as_ref(&self) -> &[u8] {
+    self.as_slice()
+  }
+}
+
+impl Debug for SharedBuf {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SharedBuf")
+      .field("range", &self.range)
+      .field("buf", &*self.buf)
+      .finish()
+  }
+}
+
+impl Deref for SharedBuf {
+  type Target = [u8];
+
+  fn deref(&self) -> &Self::Target {
+    self.as_slice()
+  }
+}
@@ -0,0 +1,73 @@
+use crate::buf::Buf;
+
+/// Splits an owned Buf into records terminated by `delim`, yielding each record as its own Buf from the same pool. Like `BufRead::split`, the delimiter isn't included, and a trailing record without one is still yielded.
+pub struct BufReaderSplit {
+  buf: Buf,
+  delim: u8,
+  pos: usize,
+}
+
+impl BufReaderSplit {
+  pub fn new(buf: Buf, delim: u8) -> Self {
+    Self { buf, delim, pos: 0 }
+  }
+
+  /// Returns the underlying Buf, including any records that haven't been yielded yet.
+  pub fn into_inner(self) -> Buf {
+    self.buf
+  }
+}
+
+impl Iterator for BufReaderSplit {
+  type Item = Buf;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let rem = &self.buf[self.pos..];
+    if rem.is_empty() {
+      return None;
+    };
+    let (record, consumed) = match memchr::memchr(self.delim, rem) {
+      Some(i) => (&rem[..i], i + 1),
+      None => (rem, rem.len()),
+    };
+    let out = self.buf.allocator().allocate_from_data(record);
+    self.pos += consumed;
+    Some(out)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::split::BufReaderSplit;
+  use crate::BufPool;
+
+  #[test]
+  fn yields_each_record_as_an_independent_buf() {
+    let pool = BufPool::new();
+    let mut buf = pool.allocate(32);
+    buf.extend_from_slice(b"one\ntwo\n\nthree");
+    let records = BufReaderSplit::new(buf, b'\n').collect::<Vec<_>>();
+    assert_eq!(
+      records.iter().map(|r| &r[..]).collect::<Vec<_>>(),
+      [&b"one"[..], b"two", b"", b"three"]
+    );
+    // The source has been dropped, but each record is still usable, and goes back to the pool on its own.
+    let mut records = records.into_iter();
+    let first = records.next().unwrap();
+    drop(records);
+    assert_eq!(&first[..], b"one");
+    assert_eq!(pool.stats().class(4).live, 1);
+    drop(first);
+    assert_eq!(pool.stats().class(4).live, 0);
+  }
+
+  #[test]
+  fn trailing_delimiter_doesnt_yield_an_empty_record() {
+    let pool = BufPool::new();
+    let mut split = BufReaderSplit::new(pool.allocate_from_data(b"a,b,"), b',');
+    assert_eq!(&split.next().unwrap()[..], b"a");
+    assert_eq!(&split.next().unwrap()[..], b"b");
+    assert!(split.next().is_none());
+    assert!(split.into_inner().ends_with(b","));
+  }
+}
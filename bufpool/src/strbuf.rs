@@ -0,0 +1,159 @@
+use crate::buf::Buf;
+use std::borrow::Borrow;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::ops::Deref;
+use std::str;
+use std::str::Utf8Error;
+
+/// Returned by `StrBuf::from_buf` when the contents aren't valid UTF-8. The Buf can be recovered with `into_buf`.
+pub struct FromUtf8Error {
+  buf: Buf,
+  error: Utf8Error,
+}
+
+impl FromUtf8Error {
+  pub fn into_buf(self) -> Buf {
+    self.buf
+  }
+
+  pub fn utf8_error(&self) -> Utf8Error {
+    self.error
+  }
+}
+
+impl Debug for FromUtf8Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("FromUtf8Error")
+      .field("buf", &self.buf)
+      .field("error", &self.error)
+      .finish()
+  }
+}
+
+impl Display for FromUtf8Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    Display::fmt(&self.error, f)
+  }
+}
+
+impl Error for FromUtf8Error {}
+
+/// A Buf whose contents are known to be valid UTF-8. It hashes and compares like a `str`, so it implements `Borrow<str>` and can be looked up in a `HashMap<StrBuf, V>` with a `&str`.
+#[derive(Clone)]
+pub struct StrBuf {
+  buf: Buf,
+}
+
+impl StrBuf {
+  /// Fails if the contents aren't valid UTF-8.
+  pub fn from_buf(buf: Buf) -> Result<Self, FromUtf8Error> {
+    match str::from_utf8(buf.as_slice()) {
+      Ok(_) => Ok(Self { buf }),
+      Err(error) => Err(FromUtf8Error { buf, error }),
+    }
+  }
+
+  pub fn as_str(&self) -> &str {
+    unsafe { str::from_utf8_unchecked(self.buf.as_slice()) }
+  }
+
+  pub fn into_buf(self) -> Buf {
+    self.buf
+  }
+}
+
+impl AsRef<str> for StrBuf {
+  fn as_ref(&self) -> &str {
+    self.as_str()
+  }
+}
+
+impl Borrow<str> for StrBuf {
+  fn borrow(&self) -> &str {
+    self.as_str()
+  }
+}
+
+impl Debug for StrBuf {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    Debug::fmt(self.as_str(), f)
+  }
+}
+
+impl Deref for StrBuf {
+  type Target = str;
+
+  fn deref(&self) -> &Self::Target {
+    self.as_str()
+  }
+}
+
+impl Display for StrBuf {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    Display::fmt(self.as_str(), f)
+  }
+}
+
+impl Eq for StrBuf {}
+
+/// Hashes like `str` (not like `Buf`), as required by `Borrow<str>`.
+impl Hash for StrBuf {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.as_str().hash(state);
+  }
+}
+
+impl PartialEq for StrBuf {
+  fn eq(&self, other: &Self) -> bool {
+    self.as_str() == other.as_str()
+  }
+}
+
+impl PartialEq<str> for StrBuf {
+  fn eq(&self, other: &str) -> bool {
+    self.as_str() == other
+  }
+}
+
+impl TryFrom<Buf> for StrBuf {
+  type Error = FromUtf8Error;
+
+  fn try_from(buf: Buf) -> Result<Self, Self::Error> {
+    Self::from_buf(buf)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::strbuf::StrBuf;
+  use crate::BufPool;
+  use std::collections::HashMap;
+
+  #[test]
+  // The pool's atomics don't affect how a StrBuf hashes.
+  #[allow(clippy::mutable_key_type)]
+  fn hash_map_lookup_by_str() {
+    let pool = BufPool::new();
+    let mut map = HashMap::new();
+    for (key, value) in [("key", 1), ("other", 2)] {
+      let key = StrBuf::from_buf(pool.allocate_from_data(key)).unwrap();
+      map.insert(key, value);
+    }
+    assert_eq!(map.get("key"), Some(&1));
+    assert_eq!(map.get("other"), Some(&2));
+    assert_eq!(map.get("missing"), None);
+  }
+
+  #[test]
+  fn from_buf_rejects_invalid_utf8() {
+    let pool = BufPool::new();
+    let err = StrBuf::from_buf(pool.allocate_from_data(b"ok\xff")).unwrap_err();
+    assert_eq!(err.utf8_error().valid_up_to(), 2);
+    assert_eq!(err.into_buf().as_slice(), b"ok\xff");
+  }
+}
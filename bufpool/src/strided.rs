@@ -0,0 +1,99 @@
+use crate::buf::Buf;
+
+/// A zeroed Buf laid out as `rows` rows of `stride` bytes, where only the first `row_len` bytes of each row are used, as is common for padded image and matrix data. Created with `BufPool::allocate_strided`.
+pub struct StridedBuf {
+  buf: Buf,
+  row_len: usize,
+  rows: usize,
+  stride: usize,
+}
+
+impl StridedBuf {
+  pub(crate) fn new(buf: Buf, row_len: usize, rows: usize, stride: usize) -> Self {
+    Self {
+      buf,
+      row_len,
+      rows,
+      stride,
+    }
+  }
+
+  /// The underlying Buf, including the padding at the end of each row.
+  pub fn into_inner(self) -> Buf {
+    self.buf
+  }
+
+  /// The `row_len` bytes of row `i`. Panics if `i` is out of bounds.
+  pub fn row(&self, i: usize) -> &[u8] {
+    assert!(
+      i < self.rows,
+      "row {i} is out of bounds for {} rows",
+      self.rows
+    );
+    let start = i * self.stride;
+    &self.buf[start..start + self.row_len]
+  }
+
+  pub fn row_mut(&mut self, i: usize) -> &mut [u8] {
+    assert!(
+      i < self.rows,
+      "row {i} is out of bounds for {} rows",
+      self.rows
+    );
+    let start = i * self.stride;
+    &mut self.buf[start..start + self.row_len]
+  }
+
+  pub fn row_len(&self) -> usize {
+    self.row_len
+  }
+
+  pub fn rows(&self) -> usize {
+    self.rows
+  }
+
+  pub fn stride(&self) -> usize {
+    self.stride
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::BufPool;
+
+  #[test]
+  fn rows_start_at_each_stride() {
+    let pool = BufPool::new();
+    let mut img = pool.allocate_strided(3, 4, 8);
+    assert_eq!((img.row_len(), img.rows(), img.stride()), (3, 4, 8));
+    for i in 0..img.rows() {
+      img.row_mut(i).fill(i as u8 + 1);
+    }
+    assert_eq!(img.row(2), &[3, 3, 3]);
+    let buf = img.into_inner();
+    assert_eq!(buf.len(), 32);
+    // Only the first `row_len` bytes of each stride were written; the padding is still zeroed.
+    assert_eq!(&buf[..8], &[1, 1, 1, 0, 0, 0, 0, 0]);
+    assert_eq!(&buf[24..], &[4, 4, 4, 0, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn row_len_can_equal_stride() {
+    let pool = BufPool::new();
+    let mut m = pool.allocate_strided(4, 2, 4);
+    m.row_mut(1).copy_from_slice(b"abcd");
+    assert_eq!(&m.into_inner()[..], b"\0\0\0\0abcd");
+  }
+
+  #[test]
+  #[should_panic(expected = "row length 5 exceeds stride 4")]
+  fn row_len_over_stride_panics() {
+    BufPool::new().allocate_strided(5, 2, 4);
+  }
+
+  #[test]
+  #[should_panic(expected = "row 4 is out of bounds for 4 rows")]
+  fn row_out_of_bounds_panics() {
+    BufPool::new().allocate_strided(3, 4, 8).row(4);
+  }
+}
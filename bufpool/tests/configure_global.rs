@@ -0,0 +1,21 @@
+// `BUFPOOL` is process-wide, so this has its own test binary, and a single test to keep the steps in order.
+
+use bufpool::configure_global;
+use bufpool::AlreadyInitialized;
+use bufpool::BUFPOOL;
+
+#[test]
+fn configure_global_only_before_first_use() {
+  assert_eq!(configure_global(4096), Ok(()));
+  // Only the first call takes effect.
+  assert_eq!(configure_global(64), Err(AlreadyInitialized));
+
+  let buf = BUFPOOL.allocate(1);
+  assert_eq!(buf.as_slice().as_ptr() as usize % 4096, 0);
+  drop(buf);
+
+  // Still refused once the pool has been used.
+  assert_eq!(configure_global(4096), Err(AlreadyInitialized));
+  let buf = BUFPOOL.allocate(100);
+  assert_eq!(buf.as_slice().as_ptr() as usize % 4096, 0);
+}